@@ -0,0 +1,182 @@
+//! The command line interface for `mdbook-trpl`, which is shared with the
+//! `mdbook-trpl-<name>` binaries for the standalone preprocessors. Each of
+//! those behaves exactly like `mdbook-trpl <name>`.
+
+use std::{env, ffi::OsString, io, path::PathBuf};
+
+use clap::{self, Parser, Subcommand};
+use mdbook::{
+    preprocess::{CmdPreprocessor, Preprocessor},
+    MDBook,
+};
+
+use mdbook_trpl::{Diagnostics, Figure, Heading, Listing, Note, Trpl};
+
+/// Run with the arguments the binary was invoked with. The binaries for the
+/// standalone preprocessors pass their `name`, so that they run as if invoked
+/// as `mdbook-trpl <name>`.
+pub fn run(name: Option<&str>) -> Result<(), String> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    if let Some(name) = name {
+        args.insert(args.len().min(1), OsString::from(name));
+    }
+    Cli::parse_from(args).run()
+}
+
+/// Preprocessors for _The Rust Programming Language_.
+///
+/// Without a subcommand, runs all of them as the `trpl` preprocessor,
+/// configured from `[preprocessor.trpl]`.
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Is the renderer supported?
+    Supports { renderer: String },
+
+    /// Check that each listing's `file-name` and `number` match the file it
+    /// includes.
+    Check {
+        /// The root directory of the book, containing its `book.toml`.
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Print the mismatches to stdout as JSON, for editors and other tools.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run only the preprocessor for semantic notes, configured from
+    /// `[preprocessor.trpl-note]`.
+    Note {
+        #[command(subcommand)]
+        command: Option<Standalone>,
+    },
+
+    /// Run only the preprocessor for code listings, configured from
+    /// `[preprocessor.trpl-listing]`.
+    Listing {
+        #[command(subcommand)]
+        command: Option<StandaloneListing>,
+    },
+
+    /// Run only the preprocessor for headings, configured from
+    /// `[preprocessor.trpl-heading]`.
+    Heading {
+        #[command(subcommand)]
+        command: Option<Standalone>,
+    },
+
+    /// Run only the preprocessor for figures, configured from
+    /// `[preprocessor.trpl-figure]`.
+    Figure {
+        #[command(subcommand)]
+        command: Option<Standalone>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Standalone {
+    /// Is the renderer supported?
+    Supports { renderer: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum StandaloneListing {
+    /// Is the renderer supported?
+    Supports { renderer: String },
+
+    /// Check that each listing's `file-name` and `number` match the file it
+    /// includes. The same as `mdbook-trpl check`.
+    Check {
+        /// The root directory of the book, containing its `book.toml`.
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Print the mismatches to stdout as JSON, for editors and other tools.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Cli {
+    fn run(self) -> Result<(), String> {
+        match self.command {
+            None => preprocess(&Trpl),
+            Some(Command::Supports { renderer }) => supports(&Trpl, &renderer),
+            Some(Command::Check { dir, json }) => check(dir, json),
+            Some(Command::Note { command }) => standalone(&Note, command),
+            Some(Command::Listing { command }) => match command {
+                None => preprocess(&Listing),
+                Some(StandaloneListing::Supports { renderer }) => {
+                    supports(&Listing, &renderer)
+                }
+                Some(StandaloneListing::Check { dir, json }) => {
+                    check(dir, json)
+                }
+            },
+            Some(Command::Heading { command }) => standalone(&Heading, command),
+            Some(Command::Figure { command }) => standalone(&Figure, command),
+        }
+    }
+}
+
+fn standalone(
+    preprocessor: &dyn Preprocessor,
+    command: Option<Standalone>,
+) -> Result<(), String> {
+    match command {
+        None => preprocess(preprocessor),
+        Some(Standalone::Supports { renderer }) => {
+            supports(preprocessor, &renderer)
+        }
+    }
+}
+
+fn supports(
+    preprocessor: &dyn Preprocessor,
+    renderer: &str,
+) -> Result<(), String> {
+    if preprocessor.supports_renderer(renderer) {
+        Ok(())
+    } else {
+        Err(format!("Renderer '{renderer}' is unsupported"))
+    }
+}
+
+fn preprocess(preprocessor: &dyn Preprocessor) -> Result<(), String> {
+    let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())
+        .map_err(|e| format!("{e}"))?;
+    let processed = preprocessor.run(&ctx, book).map_err(|e| {
+        // Errors in the book's source are printed on their own, in the format
+        // `DIAGNOSTICS_VAR` asks for, so tools can read them from stderr.
+        match e.downcast_ref::<Diagnostics>() {
+            Some(diagnostics) => {
+                diagnostics.eprint();
+                format!("Found {} error(s)", diagnostics.error_count())
+            }
+            None => format!("{e}"),
+        }
+    })?;
+    serde_json::to_writer(io::stdout(), &processed).map_err(|e| format!("{e}"))
+}
+
+fn check(dir: PathBuf, json: bool) -> Result<(), String> {
+    let md = MDBook::load(&dir).map_err(|e| format!("{e}"))?;
+    let mismatches = Diagnostics(Listing.check(&md.book, &md.config.book.src));
+    if json {
+        println!("{:#}", mismatches.to_json());
+    } else {
+        mismatches.eprint();
+    }
+
+    if mismatches.0.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Found {} listing mismatch(es)", mismatches.0.len()))
+    }
+}
@@ -0,0 +1,5 @@
+mod common;
+
+fn main() -> Result<(), String> {
+    common::run(None)
+}
@@ -0,0 +1,409 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use mdbook::{
+    book::{Book, Chapter},
+    errors::Result,
+    preprocess::{Preprocessor, PreprocessorContext},
+    BookItem,
+};
+
+use crate::{
+    config::{
+        Config, HeadingConfig, Labels, Mode, NoteConfig, PinnedId, Rewrite,
+        Transforms,
+    },
+    diagnostic::{
+        chapter_path, Diagnostic, Diagnostics, SourceError, SourceMap,
+    },
+    figure::{
+        self, check_alt_text, render_figures, simplify_figures, FigureIndex,
+    },
+    heading::{
+        anchor_pinned_ids, outline_headings, simplify_headings, write_toc,
+        TocEntry,
+    },
+    listing::{
+        assign_numbers, render_listings, resolve_references, simplify_listings,
+        ListingIndex,
+    },
+    note::{self, render_notes},
+    Spanned,
+};
+
+/// All of the preprocessors for _The Rust Programming Language_ in one.
+///
+/// It is configured from a single table:
+///
+/// ```toml
+/// [preprocessor.trpl]
+/// output-mode = "simple"
+/// rewrite = "splice"
+/// transforms = ["listing", "note", "heading", "figure"]
+/// pinned-id = "anchor"
+/// numbering = true
+/// toc = "toc.json"
+/// alt-text = "error"
+///
+/// [preprocessor.trpl.labels]
+/// listing = "Codice"
+/// file-name = "File:"
+/// figure = "Figura"
+///
+/// [preprocessor.trpl.note]
+/// prefixes = ["Nota: ", "Note: "]
+/// label = "Nota:"
+///
+/// [preprocessor.trpl.note.warning]
+/// label = "Attenzione:"
+/// ```
+///
+/// The `output-mode` and `rewrite` apply to every transformation, which each
+/// do the same thing as the corresponding standalone preprocessor: see
+/// [`Listing`], [`Note`], [`Heading`], and [`Figure`], and [`Rewrite`] for
+/// keeping the rest of the Markdown as written. The `transforms` key picks
+/// which of them run, and defaults to all of them. The `labels` table
+/// configures the listings and figures, `alt-text` the figures, and
+/// `pinned-id`, `numbering`, and `toc` the headings, just as they do for the
+/// standalone preprocessors. The `note` table takes the keys which configure
+/// the notes in `[preprocessor.trpl-note]`, so they cannot be confused with the
+/// `labels`.
+///
+/// Unlike running the standalone preprocessors one after another, all of the
+/// transformations which rewrite the structure of the Markdown share a single
+/// parse and a single serialization of each chapter. The others work on the
+/// text directly: resolving `<ListingRef>`s, `<FigureRef>`s, and the simple mode
+/// for listings before the parse, and the simple mode for notes after
+/// serializing, since it cleans up the blockquotes which serializing leaves
+/// behind.
+///
+/// [`Listing`]: crate::Listing
+/// [`Note`]: crate::Note
+/// [`Rewrite`]: crate::Rewrite
+/// [`Heading`]: crate::Heading
+/// [`Figure`]: crate::Figure
+pub struct Trpl;
+
+impl Preprocessor for Trpl {
+    fn name(&self) -> &str {
+        "trpl"
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let config = Config::from_context(ctx, self.name())?;
+        let toc = transform_book(&mut book, &ctx.config.book.src, &config)?;
+
+        if let (Some(path), Some(toc)) = (&config.heading.toc, toc) {
+            write_toc(&ctx.root.join(path), &book, toc)?;
+        }
+
+        Ok(book)
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        renderer == "html" || renderer == "markdown" || renderer == "test"
+    }
+}
+
+/// Run every transformation enabled in `config` over a single Markdown string,
+/// just as the [`Trpl`] preprocessor does for each chapter of a book.
+///
+/// The text is treated as an unnumbered chapter on its own, so any
+/// `<ListingRef>` or `<FigureRef>` must refer to a listing or figure within it,
+/// and neither `number="auto"` listings nor heading numbering are available.
+/// The `toc` in `config.heading` is ignored, since there is no book to write a
+/// table of contents for. Images without alt text are still printed as
+/// warnings unless `config.alt_text` makes them errors, or are returned along
+/// with the errors if there are any.
+///
+/// ````
+/// use mdbook_trpl::{transform, Config};
+///
+/// let text = r#"<Listing number="1-1" caption="Hello, world!">
+///
+/// ```rust
+/// fn main() {}
+/// ```
+///
+/// </Listing>"#;
+/// let rewritten = transform(text, &Config::default()).unwrap();
+/// assert!(rewritten.starts_with(r#"<figure class="listing" id="listing-1-1">"#));
+/// ````
+pub fn transform(
+    text: &str,
+    config: &Config,
+) -> std::result::Result<String, Diagnostics> {
+    let mut book = Book::new();
+    book.push_item(Chapter {
+        content: String::from(text),
+        ..Chapter::new_draft(INPUT_NAME, vec![])
+    });
+
+    transform_book(&mut book, Path::new(""), config)?;
+
+    match book.sections.pop() {
+        Some(BookItem::Chapter(chapter)) => Ok(chapter.content),
+        _ => unreachable!("the book has exactly the one chapter"),
+    }
+}
+
+/// The name errors in the text given to [`transform`] are reported against.
+const INPUT_NAME: &str = "<input>";
+
+/// Run every enabled transformation over the whole book.
+///
+/// Returns the entries for the table of contents, by chapter path, when the
+/// headings are outlined.
+fn transform_book(
+    book: &mut Book,
+    src_dir: &Path,
+    config: &Config,
+) -> std::result::Result<Option<HashMap<PathBuf, Vec<TocEntry>>>, Diagnostics> {
+    // Any warnings are reported along with the errors, if there are any, so
+    // that they are all in one list.
+    let mut errors = if config.transforms.figure {
+        check_alt_text(book, src_dir, config.alt_text)
+    } else {
+        vec![]
+    };
+
+    let index = if config.transforms.listing {
+        let index = assign_numbers(book, src_dir)
+            .and_then(|()| ListingIndex::from_book(book, src_dir));
+        match index {
+            Ok(index) => Some(index),
+            Err(index_errors) => {
+                errors.extend(index_errors);
+                return Err(Diagnostics(errors));
+            }
+        }
+    } else {
+        None
+    };
+    let figure_index = if config.transforms.figure {
+        match FigureIndex::from_book(book, src_dir) {
+            Ok(index) => Some(index),
+            Err(index_errors) => {
+                errors.extend(index_errors);
+                return Err(Diagnostics(errors));
+            }
+        }
+    } else {
+        None
+    };
+
+    let pipeline = Pipeline {
+        mode: config.mode,
+        rewrite: config.rewrite,
+        transforms: config.transforms,
+        labels: &config.labels,
+        note_config: &config.note,
+        heading_config: &config.heading,
+        index: index.as_ref(),
+        figure_index: figure_index.as_ref(),
+    };
+
+    let mut toc = HashMap::new();
+    book.for_each_mut(|item| {
+        if let BookItem::Chapter(ref mut chapter) = item {
+            let path = chapter_path(src_dir, chapter);
+            match pipeline.rewrite(chapter, &path) {
+                Ok((rewritten, entries)) => {
+                    chapter.content = rewritten;
+                    toc.insert(
+                        chapter.path.clone().unwrap_or_default(),
+                        entries,
+                    );
+                }
+                Err(diagnostics) => errors.extend(diagnostics),
+            }
+        }
+    });
+
+    let diagnostics = Diagnostics(errors);
+    if diagnostics.error_count() > 0 {
+        return Err(diagnostics);
+    }
+    diagnostics.eprint();
+
+    Ok(pipeline.outlines().then_some(toc))
+}
+
+struct Pipeline<'c> {
+    mode: Mode,
+    rewrite: Rewrite,
+    transforms: Transforms,
+    labels: &'c Labels,
+    note_config: &'c NoteConfig,
+    heading_config: &'c HeadingConfig,
+    /// Only present when the listings are transformed.
+    index: Option<&'c ListingIndex>,
+    /// Only present when the figures are transformed.
+    figure_index: Option<&'c FigureIndex>,
+}
+
+impl Pipeline<'_> {
+    /// Run all the enabled transformations over a chapter.
+    ///
+    /// `path` is the path to report errors with. Errors are reported against
+    /// the chapter's source as written, even for the steps which run after the
+    /// text has been rewritten, by mapping their offsets back through the
+    /// earlier steps.
+    ///
+    /// Along with the rewritten text, returns the chapter's entries for the
+    /// table of contents, if there is one.
+    fn rewrite(
+        &self,
+        chapter: &Chapter,
+        path: &Path,
+    ) -> std::result::Result<(String, Vec<TocEntry>), Vec<Diagnostic>> {
+        let src = chapter.content.as_str();
+        let diagnostics = |map: &SourceMap, reasons: Vec<SourceError>| {
+            reasons
+                .into_iter()
+                .map(|reason| Diagnostic::new(path, src, map.locate(reason)))
+                .collect::<Vec<_>>()
+        };
+
+        let mut text = Cow::Borrowed(src);
+        let mut map = SourceMap::default();
+        let mut entries = vec![];
+
+        if let Some(index) = self.index {
+            let (resolved, resolved_map) = resolve_references(
+                &text,
+                index,
+                chapter.path.as_deref(),
+                self.mode,
+                self.labels,
+            )
+            .map_err(|reasons| diagnostics(&map, reasons))?;
+            text = Cow::Owned(resolved);
+            map = map.then(resolved_map);
+
+            if self.mode == Mode::Simple {
+                let (simplified, simplified_map) =
+                    simplify_listings(&text, self.labels)
+                        .map_err(|reasons| diagnostics(&map, reasons))?;
+                text = Cow::Owned(simplified);
+                map = map.then(simplified_map);
+            }
+        }
+
+        if let Some(index) = self.figure_index {
+            let (resolved, resolved_map) = figure::resolve_references(
+                &text,
+                index,
+                chapter.path.as_deref(),
+                self.mode,
+                self.labels,
+            )
+            .map_err(|reasons| diagnostics(&map, reasons))?;
+            text = Cow::Owned(resolved);
+            map = map.then(resolved_map);
+        }
+
+        if self.parses() {
+            let parsed = crate::parser(&text).into_offset_iter().collect();
+            let (events, outlined) = self
+                .render(parsed, chapter)
+                .map_err(|reasons| diagnostics(&map, reasons))?;
+            entries = outlined;
+            let rewritten = crate::to_markdown(&text, events, self.rewrite)
+                .map_err(|reason| diagnostics(&map, vec![reason]))?;
+            text = Cow::Owned(rewritten);
+        }
+
+        if self.mode == Mode::Simple && self.transforms.note {
+            text = Cow::Owned(note::rewrite(
+                &text,
+                Mode::Simple,
+                self.rewrite,
+                self.note_config,
+            ));
+        }
+
+        Ok((text.into_owned(), entries))
+    }
+
+    /// Run the enabled transformations which work on the parsed events.
+    fn render<'e>(
+        &self,
+        mut events: Vec<Spanned<'e>>,
+        chapter: &Chapter,
+    ) -> std::result::Result<(Vec<Spanned<'e>>, Vec<TocEntry>), Vec<SourceError>>
+    {
+        let mut entries = vec![];
+
+        if self.transforms.figure {
+            events = render_figures(events, self.mode, self.labels)
+                .map_err(|reason| vec![reason])?;
+        }
+
+        match self.mode {
+            Mode::Default => {
+                if self.transforms.listing {
+                    events = render_listings(events, self.labels)?;
+                }
+                if self.transforms.note {
+                    events = render_notes(events, self.note_config);
+                }
+                if self.anchors_pinned_ids() {
+                    events = anchor_pinned_ids(events);
+                }
+                if self.outlines() {
+                    (events, entries) = outline_headings(
+                        events,
+                        chapter,
+                        self.heading_config.numbering,
+                    );
+                }
+            }
+            Mode::Simple => {
+                if self.transforms.heading {
+                    events = simplify_headings(events)
+                        .map_err(|reason| vec![reason])?;
+                }
+                if self.transforms.figure {
+                    events = simplify_figures(events)
+                        .map_err(|reason| vec![reason])?;
+                }
+            }
+        }
+
+        Ok((events, entries))
+    }
+
+    /// Whether any of the enabled transformations work on the parsed events.
+    fn parses(&self) -> bool {
+        match self.mode {
+            Mode::Default => {
+                self.transforms.listing
+                    || self.transforms.note
+                    || self.transforms.figure
+                    || self.anchors_pinned_ids()
+                    || self.outlines()
+            }
+            Mode::Simple => self.transforms.heading || self.transforms.figure,
+        }
+    }
+
+    fn anchors_pinned_ids(&self) -> bool {
+        self.transforms.heading
+            && self.heading_config.pinned_id == PinnedId::Anchor
+    }
+
+    /// Whether to number the headings or list them in a table of contents,
+    /// which only happens in the default mode.
+    fn outlines(&self) -> bool {
+        self.mode == Mode::Default
+            && self.transforms.heading
+            && self.heading_config.outlines()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,614 @@
+use mdbook::preprocess::CmdPreprocessor;
+
+use super::*;
+use crate::Severity;
+
+/// Run a preprocessor over a book with a single chapter, and get the chapter's
+/// rewritten content.
+fn run_on(
+    preprocessor: &dyn Preprocessor,
+    config: &str,
+    content: &str,
+) -> Result<String> {
+    let content = serde_json::to_string(content).unwrap();
+    let input_json = format!(
+        r##"[
+            {{
+                "root": "/path/to/book",
+                "config": {{
+                    "book": {{
+                        "authors": ["AUTHOR"],
+                        "language": "en",
+                        "multilingual": false,
+                        "src": "src",
+                        "title": "TITLE"
+                    }},
+                    "preprocessor": {{
+                        "trpl": {config},
+                        "trpl-listing": {config},
+                        "trpl-note": {config},
+                        "trpl-heading": {config},
+                        "trpl-figure": {config}
+                    }}
+                }},
+                "renderer": "html",
+                "mdbook_version": "0.4.21"
+            }},
+            {{
+                "sections": [
+                    {{
+                        "Chapter": {{
+                            "name": "Chapter 1",
+                            "content": {content},
+                            "number": [1],
+                            "sub_items": [],
+                            "path": "chapter_1.md",
+                            "source_path": "chapter_1.md",
+                            "parent_names": []
+                        }}
+                    }}
+                ],
+                "__non_exhaustive": null
+            }}
+        ]"##
+    );
+    let (ctx, book) =
+        CmdPreprocessor::parse_input(input_json.as_bytes()).unwrap();
+    let book = preprocessor.run(&ctx, book)?;
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("Expected a chapter");
+    };
+    Ok(chapter.content.clone())
+}
+
+/// Run the standalone preprocessors one after another, the way a book which
+/// configures each of them would.
+fn run_separately(
+    preprocessors: &[&dyn Preprocessor],
+    config: &str,
+    content: &str,
+) -> String {
+    preprocessors
+        .iter()
+        .fold(String::from(content), |content, preprocessor| {
+            run_on(*preprocessor, config, &content).unwrap()
+        })
+}
+
+fn render(markdown: &str) -> String {
+    let mut buf = String::new();
+    pulldown_cmark::html::push_html(&mut buf, crate::parser(markdown));
+    buf
+}
+
+const CHAPTER: &str = r#"# Hello, *world*
+
+> Note: This is a note.
+
+<Listing number="1-1" file-name="src/main.rs" caption="A *listing*">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+As you can see in <ListingRef number="1-1"/>, it works.
+
+<figure>
+
+<img src="picture.jpg">
+
+<figcaption>Figure 1-1: A picture</figcaption>
+
+</figure>
+"#;
+
+#[test]
+fn default_mode_matches_separate_preprocessors() {
+    let combined = run_on(&Trpl, "{}", CHAPTER).unwrap();
+    let separate = run_separately(
+        &[
+            &crate::Listing,
+            &crate::Note,
+            &crate::Heading,
+            &crate::Figure,
+        ],
+        "{}",
+        CHAPTER,
+    );
+    assert_eq!(render(&combined), render(&separate));
+    assert!(combined.contains(r#"<figure class="listing" id="listing-1-1">"#));
+    assert!(combined.contains(r#"<section class="note" aria-role="note">"#));
+}
+
+#[test]
+fn simple_mode_matches_separate_preprocessors() {
+    let config = r#"{ "output-mode": "simple" }"#;
+    let combined = run_on(&Trpl, config, CHAPTER).unwrap();
+    let separate = run_separately(
+        &[
+            &crate::Listing,
+            &crate::Heading,
+            &crate::Figure,
+            &crate::Note,
+        ],
+        config,
+        CHAPTER,
+    );
+    assert_eq!(render(&combined), render(&separate));
+    assert!(combined.contains("# Hello, world"));
+    assert!(combined.contains("> Note: This is a note."));
+    assert!(combined.contains("Listing 1-1: A *listing*"));
+    assert!(combined.contains("Figure 1-1: A picture"));
+}
+
+#[test]
+fn only_enabled_transforms() {
+    let combined =
+        run_on(&Trpl, r#"{ "transforms": ["note"] }"#, CHAPTER).unwrap();
+    assert!(combined.contains(r#"<section class="note" aria-role="note">"#));
+    assert!(combined.contains("<Listing number=\"1-1\""));
+    assert!(combined.contains(r#"<ListingRef number="1-1"/>"#));
+}
+
+#[test]
+fn shared_config() {
+    let config = r#"{
+        "note": { "prefixes": ["Nota: "], "label": "Nota:" },
+        "labels": { "listing": "Codice" }
+    }"#;
+    let combined = run_on(
+        &Trpl,
+        config,
+        "> Nota: Questo è un testo.\n\n<Listing number=\"1-1\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+    )
+    .unwrap();
+    assert!(combined.contains(r#"<section class="note" aria-role="note">"#));
+    assert!(combined.contains("Codice 1-1"));
+}
+
+#[test]
+fn invalid_transform() {
+    let err = run_on(&Trpl, r#"{ "transforms": ["listings"] }"#, CHAPTER)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid config in /path/to/book/book.toml, [preprocessor.trpl]: unknown variant `listings`, expected one of `listing`, `note`, `heading`, `figure` in `transforms`"
+    );
+}
+
+#[test]
+fn standalone_keys_are_rejected() {
+    let err = run_on(&crate::Listing, r#"{ "numbering": true }"#, CHAPTER)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid config in /path/to/book/book.toml, [preprocessor.trpl-listing]: unknown field `numbering`, expected one of `output-mode`, `rewrite`, `labels`"
+    );
+}
+
+#[test]
+fn reports_errors_with_paths() {
+    let err = run_on(&Trpl, "{}", "Some text.\n\n<Listing number=\"1-1\">\n")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error(s) rewriting input:\nsrc/chapter_1.md:3:1: error: Unclosed listing"
+    );
+}
+
+#[test]
+fn pinned_ids() {
+    let chapter = "## Regole di Ownership {#ownership-rules}\n";
+    let default = run_on(&Trpl, "{}", chapter).unwrap();
+    assert_eq!(
+        render(&default),
+        "<h2 id=\"ownership-rules\">Regole di Ownership</h2>\n"
+    );
+
+    let anchor =
+        run_on(&Trpl, r#"{ "pinned-id": "anchor" }"#, chapter).unwrap();
+    let separate =
+        run_on(&crate::Heading, r#"{ "pinned-id": "anchor" }"#, chapter)
+            .unwrap();
+    assert_eq!(render(&anchor), render(&separate));
+    assert!(anchor.contains(r#"<div id="ownership-rules"></div>"#));
+}
+
+#[test]
+fn numbering_and_toc() {
+    let toc = std::env::temp_dir()
+        .join(format!("mdbook-trpl-{}", std::process::id()))
+        .join("toc.json");
+    let config = format!(
+        r#"{{ "numbering": true, "toc": {} }}"#,
+        serde_json::to_string(&toc).unwrap()
+    );
+    let combined = run_on(&Trpl, &config, CHAPTER).unwrap();
+    let separate = run_on(&crate::Heading, &config, CHAPTER).unwrap();
+    assert!(combined.contains("1. Hello, *world*"));
+
+    // Only `trpl-heading` accepts these keys.
+    let listings_and_notes =
+        run_separately(&[&crate::Listing, &crate::Note], "{}", CHAPTER);
+    let headings =
+        run_on(&crate::Heading, &config, &listings_and_notes).unwrap();
+    assert_eq!(
+        render(&combined),
+        render(&run_on(&crate::Figure, "{}", &headings).unwrap())
+    );
+    assert!(render(&separate).contains(r#"<h1 id="hello-world">1. Hello"#));
+
+    let written = std::fs::read_to_string(&toc).unwrap();
+    std::fs::remove_dir_all(toc.parent().unwrap()).unwrap();
+    assert_eq!(
+        written,
+        r#"[
+  {
+    "chapter": "chapter_1.md",
+    "level": 1,
+    "slug": "hello-world",
+    "text": "Hello, world"
+  }
+]
+"#
+    );
+}
+
+#[test]
+fn figures() {
+    let chapter = r#"See <FigureRef number="1-1"/>.
+
+<Figure number="1-1" src="img/picture.svg" alt="A picture" caption="A *picture*" />
+"#;
+    for config in ["{}", r#"{ "output-mode": "simple" }"#] {
+        let combined = run_on(&Trpl, config, chapter).unwrap();
+        let separate = run_on(&crate::Figure, config, chapter).unwrap();
+        assert_eq!(render(&combined), render(&separate), "{config}");
+    }
+
+    let combined = run_on(&Trpl, "{}", chapter).unwrap();
+    assert!(combined.contains(r##"<a href="#figure-1-1">Figure 1-1</a>"##));
+    assert!(combined.contains(r#"<figure id="figure-1-1">"#));
+}
+
+#[test]
+fn alt_text() {
+    let chapter = "Some text.\n\n![](img/ferris.svg)\n";
+    assert!(run_on(&Trpl, "{}", chapter).is_ok());
+
+    let err = run_on(&Trpl, r#"{ "alt-text": "error" }"#, chapter).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Error(s) rewriting input:\nsrc/chapter_1.md:3:1: error: Image without alt text"
+    );
+    let separate =
+        run_on(&crate::Figure, r#"{ "alt-text": "error" }"#, chapter)
+            .unwrap_err();
+    assert_eq!(err.to_string(), separate.to_string());
+
+    let config = r#"{ "alt-text": "error", "transforms": ["listing"] }"#;
+    assert!(run_on(&Trpl, config, chapter).is_ok());
+}
+
+#[test]
+fn alt_text_warnings_with_errors() {
+    let chapter = "![](img/ferris.svg)\n\nSee <FigureRef number=\"9\"/>.\n";
+    for preprocessor in [&Trpl as &dyn Preprocessor, &crate::Figure] {
+        let err = run_on(preprocessor, "{}", chapter).unwrap_err();
+        let diagnostics = err.downcast_ref::<Diagnostics>().unwrap();
+        assert_eq!(
+            diagnostics
+                .0
+                .iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.severity))
+                .collect::<Vec<_>>(),
+            vec![(1, Severity::Warning), (3, Severity::Error)],
+            "{}",
+            preprocessor.name()
+        );
+    }
+}
+
+mod splice {
+    use super::*;
+
+    /// Markdown which reserializing would change without rendering any
+    /// differently.
+    const UNTOUCHED: &str = "* a list
+* with items
+
+> A quote
+that wraps
+
+[link]: https://example.com
+";
+
+    #[test]
+    fn keeps_untouched_markdown() {
+        let chapter = format!("{CHAPTER}\n{UNTOUCHED}");
+        let spliced =
+            run_on(&Trpl, r#"{ "rewrite": "splice" }"#, &chapter).unwrap();
+        let reserialized = run_on(&Trpl, "{}", &chapter).unwrap();
+        assert!(spliced.ends_with(&format!("</figure>\n\n{UNTOUCHED}")));
+        assert!(!reserialized.contains(UNTOUCHED));
+        assert_eq!(render(&spliced), render(&reserialized));
+    }
+
+    #[test]
+    fn replaces_only_listings_and_notes() {
+        let chapter = r#"Some *text*
+over two lines.
+
+> Note: A note.
+
+<Listing number="1-1" caption="A listing">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+"#;
+        let spliced =
+            run_on(&Trpl, r#"{ "rewrite": "splice" }"#, chapter).unwrap();
+        assert_eq!(
+            spliced,
+            r##"Some *text*
+over two lines.
+
+<section class="note" aria-role="note">
+
+Note: A note.
+
+</section>
+
+<figure class="listing" id="listing-1-1">
+
+```rust
+fn main() {}
+```
+
+<figcaption><a href="#listing-1-1">Listing 1-1</a>: A listing</figcaption>
+</figure>
+"##
+        );
+    }
+
+    #[test]
+    fn matches_separate_preprocessors() {
+        let config = r#"{ "rewrite": "splice" }"#;
+        let chapter = format!("{CHAPTER}\n{UNTOUCHED}");
+        let combined = run_on(&Trpl, config, &chapter).unwrap();
+        let separate = run_separately(
+            &[
+                &crate::Listing,
+                &crate::Note,
+                &crate::Heading,
+                &crate::Figure,
+            ],
+            config,
+            &chapter,
+        );
+        assert_eq!(combined, separate);
+    }
+}
+
+mod transform {
+    use super::*;
+    use crate::{transform, Config, ErrorKind, HeadingConfig, Transforms};
+
+    const LISTING: &str = r#"<Listing number="1-2" caption="A *caption*" file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+See <ListingRef number="1-2"/>.
+"#;
+
+    const NOTE: &str =
+        "> Note: This is some text.\n> It keeps going.\n\n> This is a blockquote.\n";
+
+    const HEADING: &str = "# This is *emphasized* and `code`\n\n## Regole di Ownership {#ownership-rules}\n";
+
+    const FIGURE: &str = r#"<Figure number="4-1" src="img/trpl04-01.svg" alt="A box and arrows" caption="A `String` in memory" />
+
+As <FigureRef number="4-1"/> shows, it works.
+"#;
+
+    #[test]
+    fn each_feature() {
+        let none = Transforms {
+            listing: false,
+            note: false,
+            heading: false,
+            figure: false,
+        };
+        let listing = Transforms {
+            listing: true,
+            ..none
+        };
+        let note = Transforms { note: true, ..none };
+        let heading = Transforms {
+            heading: true,
+            ..none
+        };
+        let figure = Transforms {
+            figure: true,
+            ..none
+        };
+
+        for (transforms, mode, pinned_id, text, expected) in [
+            (
+                listing,
+                Mode::Default,
+                PinnedId::Id,
+                LISTING,
+                "<figure class=\"listing\" id=\"listing-1-2\">\n<span class=\"file-name\">Filename: src/main.rs</span>\n\n````rust\nfn main() {}\n````\n\n<figcaption><a href=\"#listing-1-2\">Listing 1-2</a>: A <em>caption</em></figcaption>\n</figure>\n\nSee <a href=\"#listing-1-2\">Listing 1-2</a>.",
+            ),
+            (
+                listing,
+                Mode::Simple,
+                PinnedId::Id,
+                LISTING,
+                "src/main.rs\n\n```rust\nfn main() {}\n```\n\nListing 1-2: A *caption*\n\nSee Listing 1-2.\n",
+            ),
+            (
+                note,
+                Mode::Default,
+                PinnedId::Id,
+                NOTE,
+                "\n\n<section class=\"note\" aria-role=\"note\">\n\nNote: This is some text.\nIt keeps going.\n\n</section>\n\n > \n > This is a blockquote.",
+            ),
+            (note, Mode::Simple, PinnedId::Id, NOTE, NOTE),
+            (
+                heading,
+                Mode::Default,
+                PinnedId::Id,
+                HEADING,
+                HEADING,
+            ),
+            (
+                heading,
+                Mode::Default,
+                PinnedId::Anchor,
+                HEADING,
+                "# This is *emphasized* and `code`\n\n<div id=\"ownership-rules\"></div>\n\n\n\n## Regole di Ownership",
+            ),
+            (
+                heading,
+                Mode::Simple,
+                PinnedId::Id,
+                HEADING,
+                "# This is emphasized and code\n\n## Regole di Ownership",
+            ),
+            (
+                figure,
+                Mode::Default,
+                PinnedId::Id,
+                FIGURE,
+                "<figure id=\"figure-4-1\">\n\n<img src=\"img/trpl04-01.svg\" class=\"center\" alt=\"A box and arrows\" />\n\n<figcaption>Figure 4-1: A <code>String</code> in memory</figcaption>\n\n</figure>\n\nAs <a href=\"#figure-4-1\">Figure 4-1</a> shows, it works.",
+            ),
+            (
+                figure,
+                Mode::Simple,
+                PinnedId::Id,
+                FIGURE,
+                "<img src=\"img/trpl04-01.svg\" class=\"center\" alt=\"A box and arrows\" />\n\nFigure 4-1: A `String` in memory\n\nAs Figure 4-1 shows, it works.",
+            ),
+        ] {
+            let config = Config {
+                mode,
+                transforms,
+                heading: HeadingConfig {
+                    pinned_id,
+                    ..HeadingConfig::default()
+                },
+                ..Config::default()
+            };
+            assert_eq!(
+                transform(text, &config).unwrap(),
+                expected,
+                "{transforms:?} {mode:?} {pinned_id:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_preprocessor() {
+        let config = Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        };
+        assert_eq!(
+            transform(CHAPTER, &config).unwrap(),
+            run_on(&Trpl, r#"{ "output-mode": "simple" }"#, CHAPTER).unwrap()
+        );
+    }
+
+    #[test]
+    fn links_within_the_text() {
+        let rewritten = transform(CHAPTER, &Config::default()).unwrap();
+        assert!(
+            rewritten.contains(r##"<a href="#listing-1-1">Listing 1-1</a>"##)
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let err = transform(
+            "Some text.\n\n<Listing number=\"1-1\">\n",
+            &Config::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.0,
+            vec![Diagnostic {
+                path: PathBuf::from("<input>"),
+                line: 3,
+                column: 1,
+                snippet: String::from("<Listing number=\"1-1\">"),
+                kind: ErrorKind::UnclosedListing,
+                severity: Severity::Error,
+            }]
+        );
+        assert_eq!(
+            err.to_string(),
+            "Error(s) rewriting input:\n<input>:3:1: error: Unclosed listing"
+        );
+    }
+
+    #[test]
+    fn reports_errors_after_listings_at_their_source() {
+        let text = "<Listing>\n\n```rust\nfn main() {}\n```\n\n</Listing>\n\nSome text.\n\n<figure>\n\n![A picture](img.svg)\n";
+        let config = Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        };
+        let err = transform(text, &config).unwrap_err();
+        assert_eq!(
+            (err.0[0].line, &err.0[0].kind),
+            (11, &ErrorKind::UnclosedFigure)
+        );
+    }
+
+    #[test]
+    fn reports_errors_after_references_at_their_column() {
+        let text = "<Listing number=\"1-1\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n\nSee <ListingRef number=\"1-1\"/> and <FigureRef number=\"9\"/>.\n";
+        for mode in [Mode::Default, Mode::Simple] {
+            let config = Config {
+                mode,
+                ..Config::default()
+            };
+            let err = transform(text, &config).unwrap_err();
+            assert_eq!(
+                (err.0[0].line, err.0[0].column, err.0[0].snippet.as_str()),
+                (
+                    9,
+                    36,
+                    "See <ListingRef number=\"1-1\"/> and <FigureRef number=\"9\"/>."
+                ),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unnumbered() {
+        let err = transform(
+            "<Listing number=\"auto\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+            &Config::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.0[0].kind,
+            ErrorKind::UnnumberedChapter(String::from(
+                "<Listing number=\"auto\">"
+            ))
+        );
+    }
+}
@@ -1,9 +1,19 @@
 //! Get any `preprocessor.trpl-*` config.
+//!
+//! Each preprocessor's table is deserialized into a struct with exactly the
+//! keys that preprocessor supports, along with the ones mdbook itself reads, so
+//! that a typo like `output_mode` is an error instead of being ignored. A
+//! missing table is the same as an empty one.
+
+use std::path::PathBuf;
 
 use mdbook::preprocess::PreprocessorContext;
+use serde::{de, Deserialize, Deserializer};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
+    #[default]
     Default,
     Simple,
 }
@@ -13,58 +23,606 @@ impl Mode {
         ctx: &PreprocessorContext,
         preprocessor_name: &str,
     ) -> Result<Mode, Error> {
-        let config = ctx
-            .config
-            .get_preprocessor(preprocessor_name)
-            .ok_or_else(|| Error::NoConfig(preprocessor_name.into()))?;
+        Ok(Config::from_context(ctx, preprocessor_name)?.mode)
+    }
+}
+
+/// How the preprocessors turn the Markdown they rewrite back into text.
+///
+/// Configured via the `rewrite` key in the preprocessor config, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl]
+/// rewrite = "splice"
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rewrite {
+    /// Serialize the whole chapter again from its parsed events. This is the
+    /// default. It normalizes the Markdown along the way, e.g. reflowing
+    /// blockquotes and changing list markers.
+    #[default]
+    Reserialize,
+    /// Only serialize the top-level blocks which a transformation changed, such
+    /// as a `<Listing>`'s tags, a `<figure>`, or a note, and splice them into
+    /// the original text in place of the source they came from. Everything else
+    /// stays exactly as written, so the output can be diffed against the input.
+    Splice,
+}
 
-        let key = String::from("output-mode");
-        let mode = config
-            .get(&key)
-            .map(|value| match value.as_str() {
-                Some(s) => Mode::try_from(s).map_err(|_| Error::BadValue {
-                    key,
-                    value: value.to_string(),
-                }),
-                None => Err(Error::BadValue {
-                    key,
-                    value: value.to_string(),
-                }),
-            })
-            .transpose()?
-            .unwrap_or(Mode::Default);
+/// What to do with a heading's pinned ID, like `{#ownership-rules}` in
+/// `## Regole di Ownership {#ownership-rules}`.
+///
+/// Configured via the `pinned-id` key in the preprocessor config, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl-heading]
+/// pinned-id = "anchor"
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinnedId {
+    /// The pinned ID is the heading's `id`. This is the default.
+    #[default]
+    Id,
+    /// The heading keeps the `id` generated from its text, and the pinned ID is
+    /// emitted as an additional anchor right before it.
+    Anchor,
+}
 
-        Ok(mode)
+/// The configuration for headings.
+///
+/// Configured via the preprocessor config, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl-heading]
+/// pinned-id = "anchor"
+/// numbering = true
+/// toc = "toc.json"
+/// ```
+///
+/// See [`PinnedId`] for `pinned-id`. Setting `numbering` prefixes each heading
+/// in a numbered chapter with its section number, like "4.1.2", and `toc` is the
+/// path, relative to the book's root, to write a JSON table of contents to.
+/// Both only apply in the default mode, and are off unless specified.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HeadingConfig {
+    pub pinned_id: PinnedId,
+    pub numbering: bool,
+    pub toc: Option<PathBuf>,
+}
+
+impl HeadingConfig {
+    /// Whether the headings need numbering or listing in a table of contents.
+    pub(crate) fn outlines(&self) -> bool {
+        self.numbering || self.toc.is_some()
     }
 }
 
-/// Trivial marker struct to indicate an internal error.
+/// What to do about images without alt text.
+///
+/// Configured via the `alt-text` key in the preprocessor config, e.g.:
 ///
-/// The caller has enough info to do what it needs without passing data around.
-pub struct ParseErr;
+/// ```toml
+/// [preprocessor.trpl-figure]
+/// alt-text = "error"
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AltText {
+    /// Print a warning for each image, and carry on. This is the default.
+    #[default]
+    Warn,
+    /// Fail the build, reporting each image.
+    Error,
+}
 
-impl TryFrom<&str> for Mode {
-    type Error = ParseErr;
+/// Localizable labels used when rendering `<Listing>`s and `<Figure>`s.
+///
+/// Configured via a `labels` table in the preprocessor config, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl-listing.labels]
+/// listing = "Codice"
+/// file-name = "File:"
+/// highlighted-lines = "Righe evidenziate:"
+/// dimmed-lines = "Righe attenuate:"
+///
+/// [preprocessor.trpl-figure.labels]
+/// figure = "Figura"
+/// ```
+///
+/// Any label which is not specified keeps its English default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Labels {
+    /// The label for the listing number, e.g. "Listing" in "Listing 4-1".
+    pub listing: String,
+    /// The label for the file name, e.g. "Filename:" in "Filename: main.rs".
+    /// The default mode uses "Filename:" unless it is set, while the simple
+    /// mode only shows the file name on its own.
+    pub file_name: Option<String>,
+    /// The label for a listing's highlighted lines in the simple mode, e.g.
+    /// "Highlighted lines:" in "Highlighted lines: 3-5".
+    pub highlighted_lines: String,
+    /// The label for a listing's dimmed lines in the simple mode, e.g.
+    /// "Dimmed lines:" in "Dimmed lines: 1-2".
+    pub dimmed_lines: String,
+    /// The label for the figure number, e.g. "Figure" in "Figure 4-1".
+    pub figure: String,
+}
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "default" => Ok(Mode::Default),
-            "simple" => Ok(Mode::Simple),
-            _ => Err(ParseErr),
+impl Default for Labels {
+    fn default() -> Self {
+        Labels {
+            listing: String::from("Listing"),
+            file_name: None,
+            highlighted_lines: String::from("Highlighted lines:"),
+            dimmed_lines: String::from("Dimmed lines:"),
+            figure: String::from("Figure"),
         }
     }
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error(transparent)]
-    Mdbook(#[from] mdbook::errors::Error),
+/// How `TrplNote` recognizes notes, and how it labels them.
+///
+/// Configured via the `prefixes` and `label` keys in the preprocessor config,
+/// e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl-note]
+/// prefixes = ["Nota: ", "Note: "]
+/// label = "Nota:"
+/// ```
+///
+/// A blockquote is a note if its text starts with any of the prefixes. When a
+/// `label` is set, it replaces whichever prefix the note started with.
+///
+/// The warning, tip, and unsafe callouts are configured the same way, each in
+/// its own table, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl-note.warning]
+/// prefixes = ["Attenzione: ", "Warning: "]
+/// label = "Attenzione:"
+/// ```
+///
+/// Their prefixes default to `"Warning: "`, `"Tip: "`, and `"Unsafe: "`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteConfig {
+    /// The prefixes which mark a blockquote as a note, e.g. `"Note: "`.
+    pub prefixes: Vec<String>,
+    /// The label to show in place of the prefix, if any.
+    pub label: Option<String>,
+    pub warning: CalloutConfig,
+    pub tip: CalloutConfig,
+    pub unsafe_code: CalloutConfig,
+}
+
+impl Default for NoteConfig {
+    fn default() -> Self {
+        NoteConfig {
+            prefixes: default_prefixes(),
+            label: None,
+            warning: CalloutTable::default().into_config("Warning: "),
+            tip: CalloutTable::default().into_config("Tip: "),
+            unsafe_code: CalloutTable::default().into_config("Unsafe: "),
+        }
+    }
+}
+
+/// How `TrplNote` recognizes one kind of callout, and how it labels them: see
+/// [`NoteConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalloutConfig {
+    /// The prefixes which mark a blockquote as this kind of callout.
+    pub prefixes: Vec<String>,
+    /// The label to show in place of the prefix, if any.
+    pub label: Option<String>,
+}
+
+impl NoteConfig {
+    /// If `text` starts with one of the prefixes, the rest of the text.
+    pub(crate) fn strip_prefix<'t>(&self, text: &'t str) -> Option<&'t str> {
+        self.prefixes
+            .iter()
+            .find_map(|prefix| text.strip_prefix(prefix.as_str()))
+    }
+}
+
+impl CalloutConfig {
+    /// If `text` starts with one of the prefixes, that prefix and the rest of
+    /// the text.
+    pub(crate) fn split_prefix<'t>(
+        &'t self,
+        text: &'t str,
+    ) -> Option<(&'t str, &'t str)> {
+        self.prefixes.iter().find_map(|prefix| {
+            text.strip_prefix(prefix.as_str())
+                .map(|rest| (prefix.as_str(), rest))
+        })
+    }
+}
+
+/// Which transformations the combined `trpl` preprocessor runs.
+///
+/// Configured via the `transforms` key in the preprocessor config, e.g.:
+///
+/// ```toml
+/// [preprocessor.trpl]
+/// transforms = ["listing", "note"]
+/// ```
+///
+/// All of them run if it is not specified.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transforms {
+    pub listing: bool,
+    pub note: bool,
+    pub heading: bool,
+    pub figure: bool,
+}
+
+impl Default for Transforms {
+    fn default() -> Self {
+        Transforms {
+            listing: true,
+            note: true,
+            heading: true,
+            figure: true,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Transforms {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "lowercase")]
+        enum Transform {
+            Listing,
+            Note,
+            Heading,
+            Figure,
+        }
+
+        let mut transforms = Transforms {
+            listing: false,
+            note: false,
+            heading: false,
+            figure: false,
+        };
+        for transform in Vec::<Transform>::deserialize(deserializer)? {
+            let enabled = match transform {
+                Transform::Listing => &mut transforms.listing,
+                Transform::Note => &mut transforms.note,
+                Transform::Heading => &mut transforms.heading,
+                Transform::Figure => &mut transforms.figure,
+            };
+            *enabled = true;
+        }
+        Ok(transforms)
+    }
+}
+
+/// All the config for the combined `trpl` preprocessor, which is also what
+/// [`transform`] takes.
+///
+/// The default runs every transformation in the default mode, with the same
+/// defaults as each of the individual config types. The standalone
+/// preprocessors read the same struct, from a table which only accepts their
+/// own keys.
+///
+/// [`transform`]: crate::transform
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    pub mode: Mode,
+    pub rewrite: Rewrite,
+    pub transforms: Transforms,
+    pub labels: Labels,
+    pub note: NoteConfig,
+    pub heading: HeadingConfig,
+    pub alt_text: AltText,
+}
+
+impl Config {
+    /// Read the `[preprocessor.<preprocessor_name>]` table. The tables for the
+    /// standalone preprocessors, like `trpl-note`, only accept the keys which
+    /// that preprocessor uses; any other table accepts every key.
+    pub fn from_context(
+        ctx: &PreprocessorContext,
+        preprocessor_name: &str,
+    ) -> Result<Config, Error> {
+        let invalid = |message: String| Error::Invalid {
+            path: ctx.root.join("book.toml"),
+            table: format!("preprocessor.{preprocessor_name}"),
+            message,
+        };
 
-    #[error("No config for '{0}'")]
-    NoConfig(String),
+        let mut table = ctx
+            .config
+            .get_deserialized_opt::<toml::Table, _>(format!(
+                "preprocessor.{preprocessor_name}"
+            ))
+            .map_err(|e| invalid(format!("{e:#}")))?
+            .unwrap_or_default();
+        for key in MDBOOK_KEYS {
+            table.remove(key);
+        }
+
+        let table = toml::Value::Table(table);
+        match preprocessor_name {
+            "trpl-note" => table.try_into::<NoteTable>().map(Config::from),
+            "trpl-listing" => {
+                table.try_into::<ListingTable>().map(Config::from)
+            }
+            "trpl-heading" => {
+                table.try_into::<HeadingTable>().map(Config::from)
+            }
+            "trpl-figure" => table.try_into::<FigureTable>().map(Config::from),
+            _ => table.try_into::<TrplTable>().map(Config::from),
+        }
+        // The message ends with the key it is about, on a line of its own.
+        .map_err(|e| invalid(e.to_string().trim_end().replace('\n', " ")))
+    }
+}
+
+/// The keys mdbook reads from every `[preprocessor.*]` table itself.
+const MDBOOK_KEYS: [&str; 5] =
+    ["command", "renderers", "before", "after", "optional"];
+
+/// The `[preprocessor.trpl]` table, or any other than the ones below.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct TrplTable {
+    output_mode: Mode,
+    rewrite: Rewrite,
+    transforms: Transforms,
+    labels: Labels,
+    note: TrplNoteTable,
+    pinned_id: PinnedId,
+    numbering: bool,
+    #[serde(deserialize_with = "toc")]
+    toc: Option<PathBuf>,
+    alt_text: AltText,
+}
+
+impl From<TrplTable> for Config {
+    fn from(table: TrplTable) -> Self {
+        Config {
+            mode: table.output_mode,
+            rewrite: table.rewrite,
+            transforms: table.transforms,
+            labels: table.labels,
+            note: table.note.into_config(),
+            heading: HeadingConfig {
+                pinned_id: table.pinned_id,
+                numbering: table.numbering,
+                toc: table.toc,
+            },
+            alt_text: table.alt_text,
+        }
+    }
+}
+
+/// The `[preprocessor.trpl-note]` table.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct NoteTable {
+    output_mode: Mode,
+    rewrite: Rewrite,
+    #[serde(deserialize_with = "prefixes")]
+    prefixes: Vec<String>,
+    label: Option<String>,
+    warning: CalloutTable,
+    tip: CalloutTable,
+    #[serde(rename = "unsafe")]
+    unsafe_code: CalloutTable,
+}
+
+impl Default for NoteTable {
+    fn default() -> Self {
+        NoteTable {
+            output_mode: Mode::default(),
+            rewrite: Rewrite::default(),
+            prefixes: default_prefixes(),
+            label: None,
+            warning: CalloutTable::default(),
+            tip: CalloutTable::default(),
+            unsafe_code: CalloutTable::default(),
+        }
+    }
+}
+
+impl From<NoteTable> for Config {
+    fn from(table: NoteTable) -> Self {
+        Config {
+            mode: table.output_mode,
+            rewrite: table.rewrite,
+            note: TrplNoteTable {
+                prefixes: table.prefixes,
+                label: table.label,
+                warning: table.warning,
+                tip: table.tip,
+                unsafe_code: table.unsafe_code,
+            }
+            .into_config(),
+            ..Config::default()
+        }
+    }
+}
+
+/// The `[preprocessor.trpl.note]` table, with the same keys as the note ones
+/// in `[preprocessor.trpl-note]`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TrplNoteTable {
+    #[serde(deserialize_with = "prefixes")]
+    prefixes: Vec<String>,
+    label: Option<String>,
+    warning: CalloutTable,
+    tip: CalloutTable,
+    #[serde(rename = "unsafe")]
+    unsafe_code: CalloutTable,
+}
+
+impl Default for TrplNoteTable {
+    fn default() -> Self {
+        TrplNoteTable {
+            prefixes: default_prefixes(),
+            label: None,
+            warning: CalloutTable::default(),
+            tip: CalloutTable::default(),
+            unsafe_code: CalloutTable::default(),
+        }
+    }
+}
+
+impl TrplNoteTable {
+    fn into_config(self) -> NoteConfig {
+        NoteConfig {
+            prefixes: self.prefixes,
+            label: self.label,
+            warning: self.warning.into_config("Warning: "),
+            tip: self.tip.into_config("Tip: "),
+            unsafe_code: self.unsafe_code.into_config("Unsafe: "),
+        }
+    }
+}
+
+/// The `warning`, `tip`, or `unsafe` table within a note preprocessor's table.
+/// The prefixes default to the English one for the kind of callout.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CalloutTable {
+    #[serde(deserialize_with = "some_prefixes")]
+    prefixes: Option<Vec<String>>,
+    label: Option<String>,
+}
 
-    #[error("Bad config value '{value}' for key '{key}'")]
-    BadValue { key: String, value: String },
+impl CalloutTable {
+    fn into_config(self, default_prefix: &str) -> CalloutConfig {
+        CalloutConfig {
+            prefixes: self
+                .prefixes
+                .unwrap_or_else(|| vec![String::from(default_prefix)]),
+            label: self.label,
+        }
+    }
+}
+
+/// The `[preprocessor.trpl-listing]` table.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ListingTable {
+    output_mode: Mode,
+    rewrite: Rewrite,
+    labels: Labels,
+}
+
+impl From<ListingTable> for Config {
+    fn from(table: ListingTable) -> Self {
+        Config {
+            mode: table.output_mode,
+            rewrite: table.rewrite,
+            labels: table.labels,
+            ..Config::default()
+        }
+    }
+}
+
+/// The `[preprocessor.trpl-heading]` table.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct HeadingTable {
+    output_mode: Mode,
+    rewrite: Rewrite,
+    pinned_id: PinnedId,
+    numbering: bool,
+    #[serde(deserialize_with = "toc")]
+    toc: Option<PathBuf>,
+}
+
+impl From<HeadingTable> for Config {
+    fn from(table: HeadingTable) -> Self {
+        Config {
+            mode: table.output_mode,
+            rewrite: table.rewrite,
+            heading: HeadingConfig {
+                pinned_id: table.pinned_id,
+                numbering: table.numbering,
+                toc: table.toc,
+            },
+            ..Config::default()
+        }
+    }
+}
+
+/// The `[preprocessor.trpl-figure]` table.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct FigureTable {
+    output_mode: Mode,
+    rewrite: Rewrite,
+    labels: Labels,
+    alt_text: AltText,
+}
+
+impl From<FigureTable> for Config {
+    fn from(table: FigureTable) -> Self {
+        Config {
+            mode: table.output_mode,
+            rewrite: table.rewrite,
+            labels: table.labels,
+            alt_text: table.alt_text,
+            ..Config::default()
+        }
+    }
+}
+
+fn default_prefixes() -> Vec<String> {
+    vec![String::from("Note: ")]
+}
+
+/// Note prefixes must be a non-empty list of non-empty strings, or nothing
+/// would ever be a note, or everything would be.
+fn prefixes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let prefixes = Vec::<String>::deserialize(deserializer)?;
+    if prefixes.is_empty() || prefixes.iter().any(String::is_empty) {
+        return Err(de::Error::custom(
+            "expected at least one prefix, and no empty ones",
+        ));
+    }
+    Ok(prefixes)
+}
+
+fn some_prefixes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    prefixes(deserializer).map(Some)
+}
+
+fn toc<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PathBuf>, D::Error> {
+    let toc = PathBuf::deserialize(deserializer)?;
+    if toc.as_os_str().is_empty() {
+        return Err(de::Error::custom("expected a non-empty path"));
+    }
+    Ok(Some(toc))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid config in {}, [{table}]: {message}", path.display())]
+    Invalid {
+        /// The path to the `book.toml`.
+        path: PathBuf,
+        table: String,
+        message: String,
+    },
 }
 
 #[cfg(test)]
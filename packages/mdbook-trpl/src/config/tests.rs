@@ -13,7 +13,10 @@ use mdbook::{
     BookItem,
 };
 
-use crate::config::Mode;
+use crate::config::{
+    AltText, CalloutConfig, Config, HeadingConfig, Labels, Mode, NoteConfig,
+    PinnedId, Rewrite,
+};
 
 /// Dummy preprocessor for testing purposes to exercise config.
 struct TestPreprocessor;
@@ -68,10 +71,10 @@ fn no_config() {
     let input_json = input_json.as_bytes();
     let (ctx, book) =
         mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
-    let result = TestPreprocessor.run(&ctx, book);
-    assert!(result.is_err());
-    let err = result.unwrap_err();
-    assert_eq!(format!("{err}"), "No config for 'test-preprocessor'");
+    let book = TestPreprocessor.run(&ctx, book).unwrap();
+    assert!(book.iter().any(
+        |item| matches!(item, BookItem::PartTitle(title) if title == &format!("{:?}", Mode::Default))
+    ))
 }
 
 #[test]
@@ -261,6 +264,452 @@ fn specify_invalid() {
     let result = TestPreprocessor.run(&ctx, book).unwrap_err();
     assert_eq!(
         format!("{result}"),
-        "Bad config value '\"nonsense\"' for key 'output-mode'"
+        "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: unknown variant `nonsense`, expected `default` or `simple` in `output-mode`"
+    );
+}
+
+fn context_with(preprocessor_config: &str) -> PreprocessorContext {
+    context_for("test-preprocessor", preprocessor_config)
+}
+
+fn context_for(
+    preprocessor_name: &str,
+    preprocessor_config: &str,
+) -> PreprocessorContext {
+    let input_json = format!(
+        r##"[
+            {{
+                "root": "/path/to/book",
+                "config": {{
+                    "book": {{
+                        "authors": ["AUTHOR"],
+                        "language": "it",
+                        "multilingual": false,
+                        "src": "src",
+                        "title": "TITLE"
+                    }},
+                    "preprocessor": {{
+                        "{preprocessor_name}": {preprocessor_config}
+                    }}
+                }},
+                "renderer": "html",
+                "mdbook_version": "0.4.21"
+            }},
+            {{
+                "sections": [],
+                "__non_exhaustive": null
+            }}
+        ]"##
     );
+    let (ctx, _book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json.as_bytes())
+            .unwrap();
+    ctx
+}
+
+mod labels {
+    use super::*;
+
+    #[test]
+    fn default_labels() {
+        let ctx = context_with("{}");
+        let labels = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap();
+        assert_eq!(labels, Labels::default());
+    }
+
+    #[test]
+    fn all_labels() {
+        let ctx = context_with(
+            r#"{ "labels": { "listing": "Codice", "file-name": "File:", "highlighted-lines": "Righe evidenziate:", "dimmed-lines": "Righe attenuate:", "figure": "Figura" } }"#,
+        );
+        let labels = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap();
+        assert_eq!(
+            labels,
+            Labels {
+                listing: String::from("Codice"),
+                file_name: Some(String::from("File:")),
+                highlighted_lines: String::from("Righe evidenziate:"),
+                dimmed_lines: String::from("Righe attenuate:"),
+                figure: String::from("Figura"),
+            }
+        );
+    }
+
+    #[test]
+    fn some_labels() {
+        let ctx = context_with(r#"{ "labels": { "listing": "Codice" } }"#);
+        let labels = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap();
+        assert_eq!(
+            labels,
+            Labels {
+                listing: String::from("Codice"),
+                ..Labels::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_label() {
+        let ctx = context_with(r#"{ "labels": { "lisitng": "Codice" } }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: unknown field `lisitng`, expected one of `listing`, `file-name`, `highlighted-lines`, `dimmed-lines`, `figure` in `labels`"
+        );
+    }
+
+    #[test]
+    fn invalid_label() {
+        let ctx = context_with(r#"{ "labels": { "listing": 42 } }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: invalid type: integer `42`, expected a string in `labels.listing`"
+        );
+    }
+
+    #[test]
+    fn invalid_table() {
+        let ctx = context_with(r#"{ "labels": "Codice" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.labels)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: invalid type: string \"Codice\", expected struct Labels in `labels`"
+        );
+    }
+}
+
+mod note_config {
+    use super::*;
+
+    #[test]
+    fn standalone_defaults() {
+        let ctx = context_for("trpl-note", r#"{ "label": "Nota:" }"#);
+        let config = Config::from_context(&ctx, "trpl-note").unwrap();
+        assert_eq!(
+            config.note,
+            NoteConfig {
+                label: Some(String::from("Nota:")),
+                ..NoteConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn default_config() {
+        let ctx = context_with("{}");
+        let config = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.note)
+            .unwrap();
+        assert_eq!(config, NoteConfig::default());
+    }
+
+    #[test]
+    fn prefixes_and_label() {
+        let ctx = context_with(
+            r#"{ "note": { "prefixes": ["Nota: ", "Note: "], "label": "Nota:" } }"#,
+        );
+        let config = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.note)
+            .unwrap();
+        assert_eq!(
+            config,
+            NoteConfig {
+                prefixes: vec![String::from("Nota: "), String::from("Note: ")],
+                label: Some(String::from("Nota:")),
+                ..NoteConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn callouts() {
+        let ctx = context_with(
+            r#"{ "note": { "warning": { "prefixes": ["Attenzione: "], "label": "Attenzione:" }, "unsafe": { "label": "Non sicuro:" } } }"#,
+        );
+        let config = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.note)
+            .unwrap();
+        assert_eq!(
+            config.warning,
+            CalloutConfig {
+                prefixes: vec![String::from("Attenzione: ")],
+                label: Some(String::from("Attenzione:")),
+            }
+        );
+        assert_eq!(config.tip, NoteConfig::default().tip);
+        assert_eq!(
+            config.unsafe_code,
+            CalloutConfig {
+                prefixes: vec![String::from("Unsafe: ")],
+                label: Some(String::from("Non sicuro:")),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_callout_field() {
+        let ctx =
+            context_with(r#"{ "note": { "tip": { "prefix": "Nota: " } } }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.note)
+            .unwrap_err();
+        assert!(format!("{err}").contains("unknown field `prefix`"), "{err}");
+    }
+
+    #[test]
+    fn invalid_prefixes() {
+        for prefixes in [r#""Nota: ""#, "[]", r#"["Nota: ", 42]"#, r#"[""]"#] {
+            let ctx = context_with(&format!(
+                r#"{{ "note": {{ "prefixes": {prefixes} }} }}"#
+            ));
+            let err = Config::from_context(&ctx, "test-preprocessor")
+                .map(|config| config.note)
+                .unwrap_err();
+            assert!(
+                format!("{err}").ends_with("in `note.prefixes`"),
+                "{prefixes}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_label() {
+        let ctx = context_with(r#"{ "note": { "label": ["Nota:"] } }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.note)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: invalid type: sequence, expected a string in `note.label`"
+        );
+    }
+
+    #[test]
+    fn top_level_keys() {
+        let ctx = context_with(r#"{ "label": "Nota:" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor").unwrap_err();
+        assert!(format!("{err}").contains("unknown field `label`"), "{err}");
+    }
+}
+
+mod pinned_id {
+    use super::*;
+
+    #[test]
+    fn default_pinned_id() {
+        let ctx = context_with("{}");
+        let pinned = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading.pinned_id)
+            .unwrap();
+        assert_eq!(pinned, PinnedId::Id);
+    }
+
+    #[test]
+    fn anchor() {
+        let ctx = context_with(r#"{ "pinned-id": "anchor" }"#);
+        let pinned = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading.pinned_id)
+            .unwrap();
+        assert_eq!(pinned, PinnedId::Anchor);
+    }
+
+    #[test]
+    fn invalid_pinned_id() {
+        let ctx = context_with(r#"{ "pinned-id": "slug" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading.pinned_id)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: unknown variant `slug`, expected `id` or `anchor` in `pinned-id`"
+        );
+    }
+}
+
+mod heading_config {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn default_config() {
+        let ctx = context_with("{}");
+        let config = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading)
+            .unwrap();
+        assert_eq!(config, HeadingConfig::default());
+        assert!(!config.outlines());
+    }
+
+    #[test]
+    fn all_options() {
+        let ctx = context_with(
+            r#"{ "pinned-id": "anchor", "numbering": true, "toc": "toc.json" }"#,
+        );
+        let config = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading)
+            .unwrap();
+        assert_eq!(
+            config,
+            HeadingConfig {
+                pinned_id: PinnedId::Anchor,
+                numbering: true,
+                toc: Some(PathBuf::from("toc.json")),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_numbering() {
+        let ctx = context_with(r#"{ "numbering": "yes" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.heading)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: invalid type: string \"yes\", expected a boolean in `numbering`"
+        );
+    }
+
+    #[test]
+    fn invalid_toc() {
+        for toc in ["true", r#""""#] {
+            let ctx = context_with(&format!(r#"{{ "toc": {toc} }}"#));
+            let err = Config::from_context(&ctx, "test-preprocessor")
+                .map(|config| config.heading)
+                .unwrap_err();
+            assert!(format!("{err}").ends_with("in `toc`"), "{toc}: {err}");
+        }
+    }
+}
+
+mod alt_text {
+    use super::*;
+
+    #[test]
+    fn default_alt_text() {
+        let ctx = context_with("{}");
+        let alt_text = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.alt_text)
+            .unwrap();
+        assert_eq!(alt_text, AltText::Warn);
+    }
+
+    #[test]
+    fn error() {
+        let ctx = context_with(r#"{ "alt-text": "error" }"#);
+        let alt_text = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.alt_text)
+            .unwrap();
+        assert_eq!(alt_text, AltText::Error);
+    }
+
+    #[test]
+    fn invalid_alt_text() {
+        let ctx = context_with(r#"{ "alt-text": true }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor")
+            .map(|config| config.alt_text)
+            .unwrap_err();
+        assert!(format!("{err}").ends_with("in `alt-text`"), "{err}");
+    }
+}
+
+mod schema {
+    use super::*;
+
+    #[test]
+    fn unknown_key() {
+        let ctx = context_with(r#"{ "output_mode": "simple" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: unknown field `output_mode`, expected one of `output-mode`, `rewrite`, `transforms`, `labels`, `note`, `pinned-id`, `numbering`, `toc`, `alt-text`"
+        );
+    }
+
+    #[test]
+    fn mdbook_keys() {
+        let ctx = context_with(
+            r#"{
+                "command": "mdbook-trpl",
+                "renderers": ["html"],
+                "before": ["links"],
+                "after": ["index"],
+                "output-mode": "simple"
+            }"#,
+        );
+        let config = Config::from_context(&ctx, "test-preprocessor").unwrap();
+        assert_eq!(config.mode, Mode::Simple);
+    }
+
+    #[test]
+    fn missing_table() {
+        let ctx = context_with("{}");
+        let config = Config::from_context(&ctx, "trpl-note").unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn standalone_preprocessors() {
+        let ctx = context_for("trpl-listing", r#"{ "numbering": true }"#);
+        let err = Config::from_context(&ctx, "trpl-listing").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.trpl-listing]: unknown field `numbering`, expected one of `output-mode`, `rewrite`, `labels`"
+        );
+
+        let ctx = context_for("trpl-heading", r#"{ "numbering": true }"#);
+        let config = Config::from_context(&ctx, "trpl-heading").unwrap();
+        assert!(config.heading.numbering);
+
+        for name in ["trpl-note", "trpl-heading", "trpl-figure"] {
+            let ctx = context_for(name, r#"{ "transforms": ["note"] }"#);
+            let err = Config::from_context(&ctx, name).unwrap_err();
+            assert!(
+                format!("{err}").contains("unknown field `transforms`"),
+                "{name}: {err}"
+            );
+        }
+    }
+}
+
+mod rewrite {
+    use super::*;
+
+    #[test]
+    fn splice() {
+        let ctx = context_with("{}");
+        let config = Config::from_context(&ctx, "test-preprocessor").unwrap();
+        assert_eq!(config.rewrite, Rewrite::Reserialize);
+
+        for name in ["trpl-note", "trpl-listing", "trpl-heading", "trpl-figure"]
+        {
+            let ctx = context_for(name, r#"{ "rewrite": "splice" }"#);
+            let config = Config::from_context(&ctx, name).unwrap();
+            assert_eq!(config.rewrite, Rewrite::Splice, "{name}");
+        }
+    }
+
+    #[test]
+    fn invalid_rewrite() {
+        let ctx = context_with(r#"{ "rewrite": "preserve" }"#);
+        let err = Config::from_context(&ctx, "test-preprocessor").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Invalid config in /path/to/book/book.toml, [preprocessor.test-preprocessor]: unknown variant `preserve`, expected `reserialize` or `splice` in `rewrite`"
+        );
+    }
 }
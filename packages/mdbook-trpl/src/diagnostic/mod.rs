@@ -0,0 +1,419 @@
+//! Report errors with their location in a chapter's Markdown source.
+
+use std::{
+    env, fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use mdbook::book::Chapter;
+use serde_json::json;
+
+/// The environment variable which sets the format diagnostics are printed in.
+/// With `TRPL_DIAGNOSTICS=json`, they are printed as [`Diagnostics::to_json`]
+/// gives them, both by the preprocessors and by `mdbook-trpl check`; otherwise,
+/// they are printed one per line.
+pub const DIAGNOSTICS_VAR: &str = "TRPL_DIAGNOSTICS";
+
+/// An error, or a warning, at a specific location in a chapter.
+///
+/// Its `Display` implementation is a single line, like this:
+///
+/// ```text
+/// src/ch04-01-what-is-ownership.md:12:1: error: Unclosed listing
+/// ```
+///
+/// The alternate form, `{:#}`, also shows the line with the error:
+///
+/// ```text
+/// src/ch04-01-what-is-ownership.md:12:1: error: Unclosed listing
+///    |
+/// 12 | <Listing number="4-1">
+///    | ^
+/// ```
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub struct Diagnostic {
+    /// The path to the chapter, including the book's `src` directory.
+    pub path: PathBuf,
+    /// The 1-based line of the error.
+    pub line: usize,
+    /// The 1-based column of the error, in characters.
+    pub column: usize,
+    /// The full text of the line with the error.
+    pub snippet: String,
+    /// What is wrong.
+    pub kind: ErrorKind,
+    /// Whether it stops the build.
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    /// Locate an error in the `src` of the chapter at `path`. The `src` must
+    /// be the text which the error's offset refers to.
+    pub(crate) fn new(
+        path: impl Into<PathBuf>,
+        src: &str,
+        error: SourceError,
+    ) -> Diagnostic {
+        let offset = error.offset.min(src.len());
+        let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end =
+            src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+
+        Diagnostic {
+            path: path.into(),
+            line: src[..offset].matches('\n').count() + 1,
+            column: src[line_start..offset].chars().count() + 1,
+            snippet: String::from(src[line_start..line_end].trim_end()),
+            kind: error.kind,
+            severity: Severity::Error,
+        }
+    }
+
+    /// The same diagnostic, as a warning.
+    pub(crate) fn into_warning(self) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..self
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "path": self.path,
+            "line": self.line,
+            "column": self.column,
+            "severity": self.severity.to_string(),
+            "snippet": self.snippet,
+            "message": self.kind.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Diagnostic {
+            path,
+            line,
+            column,
+            snippet,
+            kind,
+            severity,
+        } = self;
+
+        write!(f, "{}:{line}:{column}: {severity}: {kind}", path.display())?;
+        if f.alternate() {
+            let gutter = " ".repeat(line.to_string().len());
+            let caret = " ".repeat(column - 1);
+            write!(f, "\n{gutter} |")?;
+            write!(f, "\n{line} | {snippet}")?;
+            write!(f, "\n{gutter} | {caret}^")?;
+        }
+        Ok(())
+    }
+}
+
+/// How serious a diagnostic is. Everything is an error, except for the
+/// problems which the configuration only asks to warn about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// All the diagnostics from a run, errors and warnings alike, reported
+/// together.
+///
+/// Its `Display` implementation shows each [`Diagnostic`] on its own line, and
+/// [`Diagnostics::to_json`] gives them in a form which editors and other tools
+/// can consume. [`Diagnostics::eprint`] prints them in whichever of those
+/// [`DIAGNOSTICS_VAR`] picks.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// A JSON array of the diagnostics, each an object with the `path`,
+    /// `line`, `column`, `severity`, `snippet`, and `message`.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.0.iter().map(Diagnostic::to_json).collect()
+    }
+
+    /// Print the diagnostics to stderr, in the format which [`DIAGNOSTICS_VAR`]
+    /// picks. Stdout is left alone, since a preprocessor writes the book there.
+    pub fn eprint(&self) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        if json_requested() {
+            eprintln!("{}", self.to_json());
+        } else {
+            for diagnostic in &self.0 {
+                eprintln!("{diagnostic}");
+            }
+        }
+    }
+
+    /// How many of the diagnostics are errors rather than warnings.
+    pub fn error_count(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .count()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error(s) rewriting input:")?;
+        for diagnostic in &self.0 {
+            write!(f, "\n{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether [`DIAGNOSTICS_VAR`] asks for diagnostics as JSON.
+fn json_requested() -> bool {
+    env::var(DIAGNOSTICS_VAR).is_ok_and(|format| format == "json")
+}
+
+/// Everything which can be wrong with a chapter's source.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ErrorKind {
+    // -- Tags and attributes
+    #[error("{0}")]
+    InvalidHtml(String),
+
+    #[error("Unsupported attribute name: '{0}'")]
+    UnsupportedAttribute(String),
+
+    #[error("Missing value for attribute: '{0}'")]
+    MissingValue(String),
+
+    #[error("Missing required attribute for `<{tag}>`: '{attribute}'")]
+    MissingAttribute {
+        tag: &'static str,
+        attribute: &'static str,
+    },
+
+    // -- Listings
+    #[error("Unclosed listing")]
+    UnclosedListing,
+
+    #[error("Closing `</Listing>` without opening tag.")]
+    UnopenedListing,
+
+    #[error("Line {0} is both highlighted and dimmed")]
+    HighlightedAndDimmed(usize),
+
+    #[error("Invalid line range '{range}' for '{attribute}': {reason}")]
+    InvalidLineRange {
+        attribute: String,
+        range: String,
+        reason: &'static str,
+    },
+
+    #[error(
+        "Overlapping line ranges for '{attribute}': '{first}' and '{second}'"
+    )]
+    OverlappingLineRanges {
+        attribute: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("'{attribute}' refers to line {line}, but the listing only has {lines} line(s) of code")]
+    LineOutOfRange {
+        attribute: &'static str,
+        line: usize,
+        lines: usize,
+    },
+
+    #[error(
+        "Cannot assign a number to a listing in an unnumbered chapter: `{0}`"
+    )]
+    UnnumberedChapter(String),
+
+    #[error("Could not find the `number` attribute in `{0}`")]
+    MissingNumber(String),
+
+    #[error("Duplicate listing name: '{0}'")]
+    DuplicateListingName(String),
+
+    #[error("Duplicate listing number: '{0}'")]
+    DuplicateListingNumber(String),
+
+    #[error("`file-name` '{file_name}' does not match the included file '{included}'")]
+    FileNameMismatch { file_name: String, included: String },
+
+    #[error("`number` '{number}' does not match the included listing directory '{directory}'")]
+    NumberMismatch { number: String, directory: String },
+
+    // -- Listing references
+    #[error("Unclosed `<ListingRef>` tag.")]
+    UnclosedListingRef,
+
+    #[error("`<ListingRef>` takes only one of 'number' or 'name'")]
+    AmbiguousListingRef,
+
+    #[error(
+        "Missing required attribute for `<ListingRef>`: 'number' or 'name'"
+    )]
+    MissingListingRefTarget,
+
+    #[error("`<ListingRef>` to nonexistent listing: '{0}'")]
+    NonexistentListing(String),
+
+    // -- Figures
+    #[error("Unclosed `<Figure>` tag: `{0}`")]
+    UnclosedFigureComponent(String),
+
+    #[error("Unexpected content after `<Figure>`: `{0}`")]
+    ContentAfterFigure(String),
+
+    #[error("A `<Figure>` has no content, so close it on the same line, or write it as `<Figure ... />`")]
+    FigureWithContent,
+
+    #[error("Duplicate figure number: '{0}'")]
+    DuplicateFigureNumber(String),
+
+    #[error("Opening `<figure>` when already in a `<figure>`")]
+    NestedFigure,
+
+    #[error("Unclosed `<figure>`")]
+    UnclosedFigure,
+
+    #[error("Unclosed `<figcaption>`")]
+    UnclosedCaption,
+
+    #[error("Missing caption in `<figcaption>`")]
+    MissingCaption,
+
+    #[error("Opening `{0}` while not in a `<figure>`.")]
+    UnexpectedOpen(&'static str),
+
+    #[error("Closing `{close}` while not in a `{required_open}`.")]
+    UnexpectedClose {
+        close: &'static str,
+        required_open: &'static str,
+    },
+
+    #[error("{0} without alt text")]
+    MissingAltText(&'static str),
+
+    // -- Figure references
+    #[error("Unclosed `<FigureRef>` tag.")]
+    UnclosedFigureRef,
+
+    #[error("`<FigureRef>` to nonexistent figure: '{0}'")]
+    NonexistentFigure(String),
+
+    // -- Headings
+    #[error("Unclosed heading")]
+    UnclosedHeading,
+
+    // -- Output
+    #[error("Could not write the Markdown: {0}")]
+    Markdown(#[from] fmt::Error),
+}
+
+/// An error along with the byte offset in the source text it refers to, before
+/// it is attached to a chapter as a [`Diagnostic`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceError {
+    pub(crate) offset: usize,
+    pub(crate) kind: ErrorKind,
+}
+
+impl SourceError {
+    pub(crate) fn new(offset: usize, kind: ErrorKind) -> SourceError {
+        SourceError { offset, kind }
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// Where the steps which rewrite a chapter's text before it is parsed, like
+/// resolving `<ListingRef>`s, moved its source around, so that errors in their
+/// output can be reported against the source as written.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct SourceMap {
+    /// The replacements each step made, in order: the range of its input which
+    /// each one replaced, and the range of the replacement in its output.
+    steps: Vec<Vec<(Range<usize>, Range<usize>)>>,
+}
+
+impl SourceMap {
+    /// Record that the step replaced `input` with `output`. Replacements must
+    /// be recorded in the order they appear in the text.
+    pub(crate) fn replace(
+        &mut self,
+        input: Range<usize>,
+        output: Range<usize>,
+    ) {
+        match self.steps.last_mut() {
+            Some(step) => step.push((input, output)),
+            None => self.steps.push(vec![(input, output)]),
+        }
+    }
+
+    /// The map for running `later` on the output of the steps in this one.
+    pub(crate) fn then(mut self, later: SourceMap) -> SourceMap {
+        self.steps.extend(later.steps);
+        self
+    }
+
+    /// The offset in the source of an offset in the output of the last step.
+    /// An offset within a replacement is at the start of what it replaced.
+    pub(crate) fn source_offset(&self, offset: usize) -> usize {
+        self.steps.iter().rev().fold(offset, |offset, step| {
+            let mut last_end = (0, 0);
+            for (input, output) in step {
+                if offset < output.start {
+                    break;
+                }
+                if offset < output.end {
+                    return input.start;
+                }
+                last_end = (input.end, output.end);
+            }
+            last_end.0 + (offset - last_end.1)
+        })
+    }
+
+    /// The same error, at its offset in the source.
+    pub(crate) fn locate(&self, error: SourceError) -> SourceError {
+        SourceError {
+            offset: self.source_offset(error.offset),
+            ..error
+        }
+    }
+}
+
+/// The path to report for errors in a chapter: its source path, including the
+/// book's `src` directory.
+pub(crate) fn chapter_path(src_dir: &Path, chapter: &Chapter) -> PathBuf {
+    let path = chapter
+        .source_path
+        .as_deref()
+        .or(chapter.path.as_deref())
+        .unwrap_or(Path::new(&chapter.name));
+    src_dir.join(path)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,214 @@
+use super::*;
+
+#[test]
+fn first_line() {
+    let diagnostic = Diagnostic::new(
+        "src/ch01-01.md",
+        "<Listing number>\n\nSome text.",
+        SourceError::new(0, ErrorKind::MissingValue(String::from("number"))),
+    );
+
+    assert_eq!(
+        diagnostic,
+        Diagnostic {
+            path: PathBuf::from("src/ch01-01.md"),
+            line: 1,
+            column: 1,
+            snippet: String::from("<Listing number>"),
+            kind: ErrorKind::MissingValue(String::from("number")),
+            severity: Severity::Error,
+        }
+    );
+}
+
+#[test]
+fn later_line_and_column() {
+    let src = "# Title\n\nSee <ListingRef number=\"9-9\"/>.\n";
+    let diagnostic = Diagnostic::new(
+        "src/ch01-01.md",
+        src,
+        SourceError::new(
+            src.find("<ListingRef").unwrap(),
+            ErrorKind::NonexistentListing(String::from("9-9")),
+        ),
+    );
+
+    assert_eq!((diagnostic.line, diagnostic.column), (3, 5));
+    assert_eq!(diagnostic.snippet, "See <ListingRef number=\"9-9\"/>.");
+}
+
+#[test]
+fn column_counts_characters() {
+    let src = "Ciò è <ListingRef/>";
+    let diagnostic = Diagnostic::new(
+        "src/ch01-01.md",
+        src,
+        SourceError::new(
+            src.find('<').unwrap(),
+            ErrorKind::MissingListingRefTarget,
+        ),
+    );
+
+    assert_eq!(diagnostic.column, 7);
+}
+
+#[test]
+fn offset_at_end() {
+    let src = "<figure>\n";
+    let diagnostic = Diagnostic::new(
+        "src/ch01-01.md",
+        src,
+        SourceError::new(src.len(), ErrorKind::UnclosedFigure),
+    );
+
+    assert_eq!((diagnostic.line, diagnostic.column), (2, 1));
+    assert_eq!(diagnostic.snippet, "");
+}
+
+#[test]
+fn display() {
+    let diagnostic = Diagnostic {
+        path: PathBuf::from("src/ch04-01-what-is-ownership.md"),
+        line: 12,
+        column: 3,
+        snippet: String::from("> <Listing number=\"4-1\">"),
+        kind: ErrorKind::UnclosedListing,
+        severity: Severity::Error,
+    };
+
+    assert_eq!(
+        diagnostic.to_string(),
+        "src/ch04-01-what-is-ownership.md:12:3: error: Unclosed listing"
+    );
+}
+
+#[test]
+fn display_with_snippet() {
+    let diagnostic = Diagnostic {
+        path: PathBuf::from("src/ch04-01-what-is-ownership.md"),
+        line: 12,
+        column: 3,
+        snippet: String::from("> <Listing number=\"4-1\">"),
+        kind: ErrorKind::UnclosedListing,
+        severity: Severity::Error,
+    };
+
+    assert_eq!(
+        format!("{diagnostic:#}"),
+        r#"src/ch04-01-what-is-ownership.md:12:3: error: Unclosed listing
+   |
+12 | > <Listing number="4-1">
+   |   ^"#
+    );
+}
+
+mod diagnostics {
+    use super::*;
+
+    fn diagnostics() -> Diagnostics {
+        Diagnostics(vec![
+            Diagnostic {
+                path: PathBuf::from("src/ch04-01.md"),
+                line: 12,
+                column: 1,
+                snippet: String::from("<Listing number=\"4-1\">"),
+                kind: ErrorKind::UnclosedListing,
+                severity: Severity::Error,
+            },
+            Diagnostic {
+                path: PathBuf::from("src/ch04-02.md"),
+                line: 3,
+                column: 5,
+                snippet: String::from("See <FigureRef number=\"9-9\"/>."),
+                kind: ErrorKind::NonexistentFigure(String::from("9-9")),
+                severity: Severity::Warning,
+            },
+        ])
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            diagnostics().to_string(),
+            r#"Error(s) rewriting input:
+src/ch04-01.md:12:1: error: Unclosed listing
+src/ch04-02.md:3:5: warning: `<FigureRef>` to nonexistent figure: '9-9'"#
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            diagnostics().to_json(),
+            json!([
+                {
+                    "path": "src/ch04-01.md",
+                    "line": 12,
+                    "column": 1,
+                    "severity": "error",
+                    "snippet": "<Listing number=\"4-1\">",
+                    "message": "Unclosed listing",
+                },
+                {
+                    "path": "src/ch04-02.md",
+                    "line": 3,
+                    "column": 5,
+                    "severity": "warning",
+                    "snippet": "See <FigureRef number=\"9-9\"/>.",
+                    "message": "`<FigureRef>` to nonexistent figure: '9-9'",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn error_count() {
+        assert_eq!(diagnostics().error_count(), 1);
+    }
+}
+
+mod source_map {
+    use super::*;
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(SourceMap::default().source_offset(12), 12);
+    }
+
+    #[test]
+    fn around_replacements() {
+        // "ab<Ref/>cd<Ref/>ef" -> "abXXXXXXXXXXcdYef"
+        let mut map = SourceMap::default();
+        map.replace(2..8, 2..12);
+        map.replace(10..16, 14..15);
+
+        assert_eq!(map.source_offset(1), 1);
+        assert_eq!(map.source_offset(5), 2);
+        assert_eq!(map.source_offset(12), 8);
+        assert_eq!(map.source_offset(14), 10);
+        assert_eq!(map.source_offset(16), 17);
+    }
+
+    #[test]
+    fn through_steps() {
+        let mut first = SourceMap::default();
+        first.replace(0..4, 0..10);
+        let mut second = SourceMap::default();
+        second.replace(12..14, 12..13);
+        let map = first.then(second);
+
+        assert_eq!(map.source_offset(11), 5);
+        assert_eq!(map.source_offset(13), 8);
+    }
+
+    #[test]
+    fn locate() {
+        let mut map = SourceMap::default();
+        map.replace(0..6, 0..1);
+
+        assert_eq!(
+            map.locate(SourceError::new(3, ErrorKind::UnclosedFigure)),
+            SourceError::new(8, ErrorKind::UnclosedFigure)
+        );
+    }
+}
@@ -1,18 +1,68 @@
-use anyhow::{anyhow, Result};
-use html_parser::{Dom, Node};
-use mdbook::{book::Book, preprocess::Preprocessor, BookItem};
-
-use pulldown_cmark::Event;
-use pulldown_cmark_to_cmark::cmark;
-
-use crate::{config::Mode, CompositeError};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+};
 
-/// A simple preprocessor to rewrite `<figure>`s with `<img>`s.
+use anyhow::Result;
+use html_parser::{Dom, Node};
+use mdbook::{
+    book::Book,
+    preprocess::{Preprocessor, PreprocessorContext},
+    BookItem,
+};
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+use crate::{
+    config::{AltText, Config, Labels, Mode, Rewrite},
+    diagnostic::{
+        chapter_path, Diagnostic, Diagnostics, ErrorKind, SourceError,
+        SourceMap,
+    },
+    listing::{self, tag_attributes},
+    Spanned,
+};
+
+/// A preprocessor for figures.
+///
+/// A numbered figure is written with a `<Figure>` component, much like a
+/// `<Listing>`:
+///
+/// ```markdown
+/// <Figure number="4-1" src="img/trpl04-01.svg" alt="A description of the image" caption="Representation in memory of a `String`" />
+/// ```
+///
+/// With no configuration, or with `output-mode = "default"`, that becomes:
+///
+/// ```markdown
+/// <figure id="figure-4-1">
 ///
-/// This is a no-op by default; it only operates on the book chapters when the
-/// `[preprocessor.trpl-figure]` has `output-mode = "simple"`.
+/// <img src="img/trpl04-01.svg" class="center" alt="A description of the image" />
 ///
-/// Takes in Markdown containing like this:
+/// <figcaption>Figure 4-1: Representation in memory of a <code>String</code></figcaption>
+///
+/// </figure>
+/// ```
+///
+/// The `number` and `src` are required, and the `alt` and `caption` optional.
+/// The "Figure" label can be localized with a `labels` table in the
+/// configuration:
+///
+/// ```toml
+/// [preprocessor.trpl-figure.labels]
+/// figure = "Figura"
+/// ```
+///
+/// Figures can be referenced from anywhere in the book with a `<FigureRef>`,
+/// like `<FigureRef number="4-1"/>`, which becomes a link to the figure, just
+/// as a `<ListingRef>` does for a listing. Referencing a number which no
+/// `<Figure>` in the book defines, or defining the same number twice, is an
+/// error.
+///
+/// When `output-mode = "simple"` in the configuration, `<Figure>`s become the
+/// image followed by its caption as text, and references become just the text,
+/// like "Figure 4-1". Figures written by hand as HTML are simplified the same
+/// way, so it takes Markdown like this:
 ///
 /// ```markdown
 /// <figure>
@@ -24,7 +74,7 @@ use crate::{config::Mode, CompositeError};
 /// </figure>
 /// ```
 ///
-/// Spits out Markdown like this:
+/// And spits out Markdown like this:
 ///
 /// ```markdown
 ///
@@ -33,89 +83,207 @@ use crate::{config::Mode, CompositeError};
 /// Figure 1-2: A description of the image
 ///
 /// ```
+///
+/// The `<figure>` and `<figcaption>` tags may have attributes, like an `id` or
+/// a `class`. Those are left exactly as they are in the default mode, and go
+/// away along with the tags in the simple mode. Everything else in the figure,
+/// including an `<img>` and its `alt` text, stays as it is in both modes.
+///
+/// In either mode, every image in the book needs alt text: each `<img>` needs a
+/// non-empty `alt` attribute, as does each `<Figure>`, and each Markdown image
+/// needs text in its brackets, as in `![A crab](img/ferris.svg)`. By default,
+/// images without it get a warning. To make them errors instead, set:
+///
+/// ```toml
+/// [preprocessor.trpl-figure]
+/// alt-text = "error"
+/// ```
 pub struct TrplFigure;
 
-impl TrplFigure {
-    pub fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html" || renderer == "markdown" || renderer == "test"
-    }
-}
-
 impl Preprocessor for TrplFigure {
     fn name(&self) -> &str {
         "trpl-figure"
     }
 
-    fn run(
-        &self,
-        ctx: &mdbook::preprocess::PreprocessorContext,
-        mut book: Book,
-    ) -> Result<Book> {
-        // The `<figure>`-based output is only replaced in the `Simple` mode.
-        let Mode::Simple = Mode::from_context(ctx, self.name())? else {
-            return Ok(book);
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let Config {
+            mode,
+            rewrite,
+            labels,
+            alt_text,
+            ..
+        } = Config::from_context(ctx, self.name())?;
+
+        let src_dir = &ctx.config.book.src;
+        // Any warnings are reported along with the errors, if there are any, so
+        // that they are all in one list.
+        let mut errors = check_alt_text(&book, src_dir, alt_text);
+        let index = match FigureIndex::from_book(&book, src_dir) {
+            Ok(index) => index,
+            Err(index_errors) => {
+                errors.extend(index_errors);
+                return Err(Diagnostics(errors).into());
+            }
         };
 
-        let mut errors = vec![];
         book.for_each_mut(|item| {
             if let BookItem::Chapter(ref mut chapter) = item {
-                match rewrite_figure(&chapter.content) {
+                let path = chapter_path(src_dir, chapter);
+                let (resolved, map) = match resolve_references(
+                    &chapter.content,
+                    &index,
+                    chapter.path.as_deref(),
+                    mode,
+                    &labels,
+                ) {
+                    Ok(resolved) => resolved,
+                    Err(reasons) => {
+                        errors.extend(reasons.into_iter().map(|reason| {
+                            Diagnostic::new(&path, &chapter.content, reason)
+                        }));
+                        return;
+                    }
+                };
+
+                match rewrite_figure(&resolved, mode, rewrite, &labels) {
                     Ok(rewritten) => chapter.content = rewritten,
-                    Err(reason) => errors.push(reason),
+                    Err(reason) => errors.push(Diagnostic::new(
+                        &path,
+                        &chapter.content,
+                        map.locate(reason),
+                    )),
                 }
             }
         });
 
-        if errors.is_empty() {
-            Ok(book)
-        } else {
-            Err(CompositeError(errors).into())
+        let diagnostics = Diagnostics(errors);
+        if diagnostics.error_count() > 0 {
+            return Err(diagnostics.into());
         }
+        diagnostics.eprint();
+        Ok(book)
     }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        renderer == "html" || renderer == "markdown" || renderer == "test"
+    }
+}
+
+const OPEN_FIGURE: &str = "<figure>";
+const CLOSE_FIGURE: &str = "</figure>";
+
+const OPEN_CAPTION: &str = "<figcaption>";
+const CLOSE_CAPTION: &str = "</figcaption>";
+
+fn rewrite_figure(
+    text: &str,
+    mode: Mode,
+    rewrite: Rewrite,
+    labels: &Labels,
+) -> Result<String, SourceError> {
+    // Hand-written figures are left exactly as they are in the default mode, so
+    // there is nothing to do without any `<Figure>`s.
+    if mode == Mode::Default && !text.contains("<Figure") {
+        return Ok(text.into());
+    }
+
+    let events = crate::parser(text).into_offset_iter();
+    let events = render_figures(events, mode, labels)?;
+    let events = match mode {
+        Mode::Default => events,
+        Mode::Simple => simplify_figures(events)?,
+    };
+    crate::to_markdown(text, events, rewrite)
 }
 
-const OPEN_FIGURE: &'static str = "<figure>";
-const CLOSE_FIGURE: &'static str = "</figure>";
+/// Replace each `<Figure>` with its HTML in the default mode, or with its image
+/// and caption in the simple mode.
+pub(crate) fn render_figures<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+    mode: Mode,
+    labels: &Labels,
+) -> Result<Vec<Spanned<'e>>, SourceError> {
+    events
+        .into_iter()
+        .map(|(event, range)| match event {
+            Event::Html(html) if is_figure_component(html.trim_start()) => {
+                let figure = FigureComponent::from_line(&html)
+                    .map_err(|reason| SourceError::new(range.start, reason))?;
+                let rendered = match mode {
+                    Mode::Default => figure.html(labels),
+                    Mode::Simple => figure.text(labels),
+                };
+                Ok((Event::Html(rendered.into()), range))
+            }
+
+            Event::Html(html) if html.trim() == "</Figure>" => {
+                Err(SourceError::new(range.start, ErrorKind::FigureWithContent))
+            }
 
-const OPEN_CAPTION: &'static str = "<figcaption>";
-const CLOSE_CAPTION: &'static str = "</figcaption>";
+            event => Ok((event, range)),
+        })
+        .collect()
+}
 
-fn rewrite_figure(text: &str) -> Result<String> {
-    let final_state = crate::parser(text).try_fold(
+/// Replace `<figure>`s with their contents, and their `<figcaption>`s with the
+/// text of the caption.
+pub(crate) fn simplify_figures<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+) -> Result<Vec<Spanned<'e>>, SourceError> {
+    let final_state = events.into_iter().try_fold(
         State {
             current: None,
             events: Vec::new(),
         },
-        |mut state, event| {
-            match (event, &mut state.current) {
+        |mut state, (event, range)| {
+            let error = |kind: ErrorKind| SourceError::new(range.start, kind);
+
+            let tag = match &event {
+                Event::Html(html) => FigureTag::parse(html).map_err(error)?,
+                _ => None,
+            };
+
+            match (tag, &mut state.current) {
                 // -- Open figure
-                (Event::Html(tag), None) if tag.starts_with(OPEN_FIGURE) => {
-                    let mut figure = Figure::new();
-                    figure.events.push(Event::Text("\n".into()));
+                // Any attributes, like an `id` or a `class`, are only for the
+                // HTML output, so they go away along with the `<figure>`.
+                (Some((FigureTag::OpenFigure, rest)), None) => {
+                    let mut figure = Figure::new(range.start);
+                    figure
+                        .events
+                        .push((Event::Text("\n".into()), range.clone()));
+                    if !rest.trim().is_empty() {
+                        figure.events.push((
+                            Event::Html(rest.to_string().into()),
+                            range,
+                        ));
+                    }
                     state.current.replace(figure);
                 }
 
-                (Event::Html(tag), Some(_)) if tag.starts_with(OPEN_FIGURE) => {
-                    return Err(anyhow!(
-                        "Opening `<figure>` when already in a `<figure>`"
-                    ))
+                (Some((FigureTag::OpenFigure, _)), Some(_)) => {
+                    return Err(error(ErrorKind::NestedFigure))
                 }
 
                 // -- Close figure
-                (Event::Html(tag), Some(figure))
-                    if tag.starts_with(CLOSE_FIGURE) =>
-                {
+                (Some((FigureTag::CloseFigure, _)), Some(figure)) => {
                     if figure.in_caption {
-                        return Err(anyhow!("Unclosed `<figcaption>`"));
+                        return Err(SourceError::new(
+                            figure.caption_start,
+                            ErrorKind::UnclosedCaption,
+                        ));
                     }
 
                     state.events.append(&mut figure.events);
-                    state.events.push(Event::Text("\n".into()));
+                    state.events.push((Event::Text("\n".into()), range));
                     let _ = state.current.take();
                 }
 
-                (Event::Html(tag), None) if tag.trim() == CLOSE_FIGURE => {
-                    return Err(anyhow!(bad_close(CLOSE_FIGURE, OPEN_CAPTION)));
+                (Some((FigureTag::CloseFigure, _)), None) => {
+                    return Err(error(ErrorKind::UnexpectedClose {
+                        close: CLOSE_FIGURE,
+                        required_open: OPEN_FIGURE,
+                    }));
                 }
 
                 // -- Start captions
@@ -123,77 +291,561 @@ fn rewrite_figure(text: &str) -> Result<String> {
                 // started a caption, it is legal to start one, and we
                 // intentionally ignore that event entirely other than tracking
                 // that we have started a caption. We will push the body of the
-                // caption into the figure’s events when we hit them.
-                //
-                // Note: this does not support `<figcaption class="...">`.
-                (Event::Html(tag), Some(fig))
-                    if tag.starts_with(OPEN_CAPTION) =>
-                {
+                // caption into the figure’s events when we hit them. As with
+                // the `<figure>`, any attributes go away with the tag.
+                (Some((FigureTag::OpenCaption, rest)), Some(fig)) => {
                     if fig.in_caption {
-                        return Err(anyhow!(bad_open(OPEN_CAPTION)));
-                    } else {
-                        if tag.trim().ends_with(CLOSE_CAPTION) {
-                            let text = Dom::parse(tag.as_ref())?
-                                .children
-                                .into_iter()
-                                .filter_map(text_of)
-                                .collect::<String>();
-
-                            if text.is_empty() {
-                                return Err(anyhow!(
-                                    "Missing caption in `<figcaption>`"
-                                ));
-                            }
-
-                            fig.events.push(Event::Text(text.into()));
-                        } else {
-                            fig.events.push(Event::Text("\n".into()));
-                            fig.in_caption = true;
+                        return Err(error(ErrorKind::UnexpectedOpen(
+                            OPEN_CAPTION,
+                        )));
+                    } else if rest.trim_end().ends_with(CLOSE_CAPTION) {
+                        let Event::Html(html) = &event else {
+                            unreachable!("only HTML has figure tags");
+                        };
+                        let text = Dom::parse(html.trim())
+                            .map_err(|e| {
+                                error(ErrorKind::InvalidHtml(e.to_string()))
+                            })?
+                            .children
+                            .into_iter()
+                            .filter_map(text_of)
+                            .collect::<String>();
+
+                        if text.is_empty() {
+                            return Err(error(ErrorKind::MissingCaption));
                         }
+
+                        fig.events.push((Event::Text(text.into()), range));
+                    } else {
+                        fig.caption_start = range.start;
+                        fig.events.push((Event::Text("\n".into()), range));
+                        fig.in_caption = true;
                     }
                 }
 
-                (Event::Html(tag), None) if tag.starts_with(OPEN_CAPTION) => {
-                    return Err(anyhow!(bad_open(OPEN_CAPTION)))
+                (Some((FigureTag::OpenCaption, _)), None) => {
+                    return Err(error(ErrorKind::UnexpectedOpen(OPEN_CAPTION)))
                 }
 
                 // -- Close captions
-                (Event::Html(tag), Some(fig))
-                    if tag.trim() == CLOSE_CAPTION =>
-                {
+                (Some((FigureTag::CloseCaption, _)), Some(fig)) => {
                     if fig.in_caption {
-                        fig.events.push(Event::Text("\n".into()));
+                        fig.events.push((Event::Text("\n".into()), range));
                         fig.in_caption = false;
                     } else {
-                        return Err(anyhow!(bad_close(
-                            CLOSE_CAPTION,
-                            OPEN_CAPTION
-                        )));
+                        return Err(error(ErrorKind::UnexpectedClose {
+                            close: CLOSE_CAPTION,
+                            required_open: OPEN_CAPTION,
+                        }));
                     }
                 }
 
-                (Event::Html(tag), None) if tag.trim() == CLOSE_CAPTION => {
-                    return Err(anyhow!(bad_close(CLOSE_CAPTION, OPEN_FIGURE)));
+                (Some((FigureTag::CloseCaption, _)), None) => {
+                    return Err(error(ErrorKind::UnexpectedClose {
+                        close: CLOSE_CAPTION,
+                        required_open: OPEN_FIGURE,
+                    }));
                 }
 
                 // Otherwise, if in the body of a figure, push whatever other
-                // events without modification into the figure state.
-                (ev, Some(ref mut figure)) => figure.events.push(ev),
+                // events without modification into the figure state. That
+                // includes any `<img>`, with its `alt` text.
+                (None, Some(ref mut figure)) => {
+                    figure.events.push((event, range))
+                }
 
                 // And if not in a figure, no modifications whatsoever.
-                (ev, None) => state.events.push(ev),
+                (None, None) => state.events.push((event, range)),
             }
             Ok(state)
         },
     )?;
 
-    if final_state.current.is_some() {
-        return Err(anyhow!("Unclosed `<figure>`"));
+    if let Some(figure) = final_state.current {
+        return Err(SourceError::new(figure.start, ErrorKind::UnclosedFigure));
+    }
+
+    Ok(final_state.events)
+}
+
+/// The `<figure>` and `<figcaption>` tags, which may have attributes when
+/// opening them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FigureTag {
+    OpenFigure,
+    CloseFigure,
+    OpenCaption,
+    CloseCaption,
+}
+
+impl FigureTag {
+    /// Recognize a figure tag at the start of a line of HTML, along with the
+    /// rest of the line after it.
+    ///
+    /// Opening tags are parsed with [`Dom`], the same as `<Listing>` tags, so
+    /// that malformed attributes are reported rather than passed through.
+    fn parse(html: &str) -> Result<Option<(FigureTag, &str)>, ErrorKind> {
+        let html = html.trim_start();
+        let Some(end) = tag_end(html) else {
+            return Ok(None);
+        };
+        let (tag, rest) = html.split_at(end);
+
+        if let Some(close) = tag.strip_prefix("</") {
+            let figure_tag = match close.trim_end_matches('>').trim_end() {
+                "figure" => FigureTag::CloseFigure,
+                "figcaption" => FigureTag::CloseCaption,
+                _ => return Ok(None),
+            };
+            return Ok(Some((figure_tag, rest)));
+        }
+
+        let name = tag[1..]
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default();
+        let figure_tag = match name {
+            "figure" => FigureTag::OpenFigure,
+            "figcaption" => FigureTag::OpenCaption,
+            _ => return Ok(None),
+        };
+
+        Dom::parse(&format!("{tag}</{name}>"))
+            .map_err(|e| ErrorKind::InvalidHtml(e.to_string()))?;
+        Ok(Some((figure_tag, rest)))
+    }
+}
+
+/// The offset just past the end of the tag at the start of `html`, if it starts
+/// with one, skipping any `>` in quoted attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+    if !html.starts_with('<') {
+        return None;
+    }
+
+    let mut quote = None;
+    for (index, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return Some(index + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Check that every image in the book has alt text, reporting any which do not
+/// as warnings or as errors, depending on `alt_text`.
+pub(crate) fn check_alt_text(
+    book: &Book,
+    src_dir: &Path,
+    alt_text: AltText,
+) -> Vec<Diagnostic> {
+    let missing = book
+        .iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => Some(chapter),
+            _ => None,
+        })
+        .flat_map(|chapter| {
+            let path = chapter_path(src_dir, chapter);
+            missing_alt_text(&chapter.content)
+                .into_iter()
+                .map(move |reason| {
+                    Diagnostic::new(&path, &chapter.content, reason)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    match alt_text {
+        AltText::Warn => {
+            missing.into_iter().map(Diagnostic::into_warning).collect()
+        }
+        AltText::Error => missing,
+    }
+}
+
+/// Find every image in a chapter without alt text: Markdown images, `<img>`
+/// tags, and `<Figure>`s.
+fn missing_alt_text(src: &str) -> Vec<SourceError> {
+    let mut missing = vec![];
+    // The start of the Markdown image currently being read, and its alt text
+    // so far.
+    let mut image: Option<(usize, String)> = None;
+
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Image { .. }) => {
+                image = Some((range.start, String::new()));
+            }
+
+            Event::End(TagEnd::Image) => {
+                if let Some((start, alt)) = image.take() {
+                    if alt.trim().is_empty() {
+                        missing.push(SourceError::new(
+                            start,
+                            ErrorKind::MissingAltText("Image"),
+                        ));
+                    }
+                }
+            }
+
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, alt)) = &mut image {
+                    alt.push_str(&text);
+                }
+            }
+
+            Event::Html(html) if is_figure_component(html.trim_start()) => {
+                // Malformed tags are reported when rewriting the chapter.
+                let Ok(figure) = FigureComponent::from_line(&html) else {
+                    continue;
+                };
+                if figure.alt.is_none_or(|alt| alt.trim().is_empty()) {
+                    missing.push(SourceError::new(
+                        range.start,
+                        ErrorKind::MissingAltText("`<Figure>`"),
+                    ));
+                }
+            }
+
+            Event::Html(_) | Event::InlineHtml(_) => {
+                for (offset, _) in src[range.clone()].match_indices("<img") {
+                    let start = range.start + offset;
+                    if !img_has_alt_text(&src[start..]) {
+                        missing.push(SourceError::new(
+                            start,
+                            ErrorKind::MissingAltText("`<img>`"),
+                        ));
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    missing
+}
+
+/// Whether the `<img>` tag at the start of `html` has a non-empty `alt`.
+/// Anything else which happens to start with `<img`, like an `<imgur-embed>`,
+/// does not need one.
+fn img_has_alt_text(html: &str) -> bool {
+    let is_img = html["<img".len()..]
+        .starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace());
+    let Some(end) = tag_end(html).filter(|_| is_img) else {
+        return true;
+    };
+
+    tag_attributes(&html[..end]).is_ok_and(|mut attributes| {
+        attributes.any(|(key, value)| {
+            key == "alt" && value.is_some_and(|alt| !alt.trim().is_empty())
+        })
+    })
+}
+
+/// Is this a `<Figure>` component, as opposed to e.g. a `<FigureRef>` or an
+/// HTML `<figure>`?
+fn is_figure_component(tag: &str) -> bool {
+    tag.strip_prefix("<Figure").is_some_and(|rest| {
+        rest.starts_with(|c: char| {
+            c == '>' || c == '/' || c.is_ascii_whitespace()
+        })
+    })
+}
+
+/// A `<Figure>` component.
+#[derive(Debug, PartialEq)]
+struct FigureComponent {
+    number: String,
+    src: String,
+    alt: Option<String>,
+    caption: Option<String>,
+}
+
+impl FigureComponent {
+    /// Parse a line with a `<Figure>` tag, which may be closed right after it
+    /// with `</Figure>`.
+    fn from_line(line: &str) -> Result<FigureComponent, ErrorKind> {
+        let line = line.trim();
+        let end = tag_end(line)
+            .ok_or_else(|| ErrorKind::UnclosedFigureComponent(line.into()))?;
+        let (tag, rest) = line.split_at(end);
+        if !rest.is_empty() && rest != "</Figure>" {
+            return Err(ErrorKind::ContentAfterFigure(rest.into()));
+        }
+        FigureComponent::from_tag(tag)
+    }
+
+    fn from_tag(tag: &str) -> Result<FigureComponent, ErrorKind> {
+        let to_parse = match tag.strip_suffix("/>") {
+            Some(_) => tag.to_string(),
+            None => format!("{tag}</Figure>"),
+        };
+
+        let mut number = None;
+        let mut src = None;
+        let mut alt = None;
+        let mut caption = None;
+        for (key, value) in tag_attributes(&to_parse)? {
+            let attribute = match key.as_str() {
+                "number" => &mut number,
+                "src" => &mut src,
+                "alt" => &mut alt,
+                "caption" => &mut caption,
+                attr => {
+                    return Err(ErrorKind::UnsupportedAttribute(attr.into()))
+                }
+            };
+            let value =
+                value.ok_or_else(|| ErrorKind::MissingValue(key.clone()))?;
+            *attribute = Some(value);
+        }
+
+        let required = |value: Option<String>, attribute| {
+            value.ok_or(ErrorKind::MissingAttribute {
+                tag: "Figure",
+                attribute,
+            })
+        };
+
+        Ok(FigureComponent {
+            number: required(number, "number")?,
+            src: required(src, "src")?,
+            alt,
+            caption,
+        })
+    }
+
+    fn img(&self) -> String {
+        let src = escape_attribute(&self.src);
+        let alt = self
+            .alt
+            .as_ref()
+            .map(|alt| format!(" alt=\"{}\"", escape_attribute(alt)))
+            .unwrap_or_default();
+        format!("<img src=\"{src}\" class=\"center\"{alt} />")
+    }
+
+    fn html(&self, labels: &Labels) -> String {
+        let number = &self.number;
+        let img = self.img();
+        let label = &labels.figure;
+        let caption = self
+            .caption
+            .as_ref()
+            .map(|caption| format!(": {}", crate::inline_html(caption)))
+            .unwrap_or_default();
+        format!(
+            r#"<figure id="figure-{number}">
+
+{img}
+
+<figcaption>{label} {number}{caption}</figcaption>
+
+</figure>
+"#
+        )
+    }
+
+    fn text(&self, labels: &Labels) -> String {
+        let number = &self.number;
+        let img = self.img();
+        let label = &labels.figure;
+        let caption = self
+            .caption
+            .as_ref()
+            .map(|caption| format!(": {caption}"))
+            .unwrap_or_default();
+        format!("{img}\n\n{label} {number}{caption}\n")
+    }
+}
+
+/// Attribute values are passed through as they are written, entities and all,
+/// so the only thing to escape is a `"` from a single-quoted value.
+fn escape_attribute(value: &str) -> String {
+    value.replace('"', "&quot;")
+}
+
+/// All the figure numbers defined anywhere in the book, along with the path to
+/// the chapter which defines each of them, so that `<FigureRef>`s can link to
+/// them.
+#[derive(Debug, Default)]
+pub(crate) struct FigureIndex {
+    chapters: HashMap<String, Option<PathBuf>>,
+}
+
+impl FigureIndex {
+    pub(crate) fn from_book(
+        book: &Book,
+        src_dir: &Path,
+    ) -> Result<FigureIndex, Vec<Diagnostic>> {
+        let mut index = FigureIndex::default();
+        let mut errors = vec![];
+        for item in book.iter() {
+            if let BookItem::Chapter(chapter) = item {
+                for error in
+                    index.add_chapter(&chapter.content, chapter.path.as_deref())
+                {
+                    errors.push(Diagnostic::new(
+                        chapter_path(src_dir, chapter),
+                        &chapter.content,
+                        error,
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(index)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Record every `<Figure>` in the chapter. Malformed tags are ignored
+    /// here; they are reported when rewriting the chapter.
+    fn add_chapter(
+        &mut self,
+        src: &str,
+        path: Option<&Path>,
+    ) -> Vec<SourceError> {
+        let mut errors = vec![];
+        for (event, range) in crate::parser(src).into_offset_iter() {
+            let Event::Html(html) = event else {
+                continue;
+            };
+
+            if !is_figure_component(html.trim_start()) {
+                continue;
+            }
+
+            let Ok(figure) = FigureComponent::from_line(&html) else {
+                continue;
+            };
+
+            match self.chapters.entry(figure.number) {
+                Entry::Occupied(entry) => errors.push(SourceError::new(
+                    range.start,
+                    ErrorKind::DuplicateFigureNumber(entry.key().clone()),
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert(path.map(PathBuf::from));
+                }
+            }
+        }
+        errors
     }
 
-    let mut rewritten = String::new();
-    cmark(final_state.events.into_iter(), &mut rewritten)?;
-    Ok(rewritten)
+    /// Get the link to a figure from the chapter at `from`.
+    fn href(&self, number: &str, from: Option<&Path>) -> Option<String> {
+        let target = self.chapters.get(number)?;
+        Some(listing::href(
+            target.as_deref(),
+            from,
+            &format!("figure-{number}"),
+        ))
+    }
+}
+
+/// Replace every `<FigureRef number="..."/>` in the text with a reference to
+/// the corresponding figure: a link in the default mode, and plain text in the
+/// simple mode.
+pub(crate) fn resolve_references(
+    src: &str,
+    index: &FigureIndex,
+    path: Option<&Path>,
+    mode: Mode,
+    labels: &Labels,
+) -> Result<(String, SourceMap), Vec<SourceError>> {
+    let mut rewritten = String::with_capacity(src.len());
+    let mut map = SourceMap::default();
+    let mut errors = vec![];
+    let mut last_end = 0;
+
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        let (Event::Html(_) | Event::InlineHtml(_)) = event else {
+            continue;
+        };
+
+        // An HTML block can contain any number of references.
+        let mut next = range.start;
+        while let Some(start) = src[next..range.end]
+            .find("<FigureRef")
+            .map(|offset| next + offset)
+        {
+            let Some(end) =
+                tag_end(&src[start..range.end]).map(|end| start + end)
+            else {
+                errors.push(SourceError::new(
+                    start,
+                    ErrorKind::UnclosedFigureRef,
+                ));
+                break;
+            };
+            next = end;
+
+            let number = match figure_ref_number(&src[start..end]) {
+                Ok(number) => number,
+                Err(reason) => {
+                    errors.push(SourceError::new(start, reason));
+                    continue;
+                }
+            };
+
+            let Some(href) = index.href(&number, path) else {
+                errors.push(SourceError::new(
+                    start,
+                    ErrorKind::NonexistentFigure(number),
+                ));
+                continue;
+            };
+
+            let label = &labels.figure;
+            rewritten.push_str(&src[last_end..start]);
+            let replacement_start = rewritten.len();
+            match mode {
+                Mode::Default => rewritten.push_str(&format!(
+                    "<a href=\"{href}\">{label} {number}</a>"
+                )),
+                Mode::Simple => {
+                    rewritten.push_str(&format!("{label} {number}"))
+                }
+            }
+            map.replace(start..end, replacement_start..rewritten.len());
+            last_end = end;
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    rewritten.push_str(&src[last_end..]);
+    Ok((rewritten, map))
+}
+
+/// Get the number from a `<FigureRef number="..."/>` tag.
+fn figure_ref_number(tag: &str) -> Result<String, ErrorKind> {
+    let mut number = None;
+    for (key, value) in tag_attributes(tag)? {
+        match (key.as_str(), value) {
+            ("number", Some(value)) => number = Some(value),
+            ("number", None) => {
+                return Err(ErrorKind::MissingValue(String::from("number")))
+            }
+            (attr, _) => {
+                return Err(ErrorKind::UnsupportedAttribute(attr.into()))
+            }
+        }
+    }
+
+    number.ok_or(ErrorKind::MissingAttribute {
+        tag: "FigureRef",
+        attribute: "number",
+    })
 }
 
 fn text_of(node: Node) -> Option<String> {
@@ -206,31 +858,29 @@ fn text_of(node: Node) -> Option<String> {
     }
 }
 
-fn bad_open(tag: &str) -> String {
-    format!("Opening `<{tag}>` while not in a `<figure>`.")
-}
-
-fn bad_close(close: &str, required_open: &str) -> String {
-    format!("Closing `<{close}>` while not in a `<{required_open}>`.")
-}
-
 #[derive(Debug)]
 struct State<'e> {
     current: Option<Figure<'e>>,
-    events: Vec<Event<'e>>,
+    events: Vec<Spanned<'e>>,
 }
 
 #[derive(Debug)]
 struct Figure<'e> {
-    events: Vec<Event<'e>>,
+    events: Vec<Spanned<'e>>,
     in_caption: bool,
+    /// The offsets of the opening `<figure>` and `<figcaption>` tags in the
+    /// source, for reporting unclosed tags.
+    start: usize,
+    caption_start: usize,
 }
 
 impl<'e> Figure<'e> {
-    fn new() -> Figure<'e> {
+    fn new(start: usize) -> Figure<'e> {
         Figure {
             events: vec![],
             in_caption: false,
+            start,
+            caption_start: start,
         }
     }
 }
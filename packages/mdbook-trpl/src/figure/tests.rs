@@ -1,8 +1,15 @@
 use super::*;
+use crate::config::Rewrite;
 
 #[test]
 fn text_without_figures_is_ignored() {
-    let actual = rewrite_figure("This is some basic text.").unwrap();
+    let actual = rewrite_figure(
+        "This is some basic text.",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap();
     assert_eq!(actual, "This is some basic text.");
 }
 
@@ -16,6 +23,9 @@ fn text_with_figure_replaces_it_with_simple_text() {
 <figcaption>Figure 12-34: Look at this cool picture!</figcaption>
 
 </figure>"#,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
     )
     .unwrap();
 
@@ -32,8 +42,13 @@ Figure 12-34: Look at this cool picture!
 
 #[test]
 fn unclosed_figure() {
-    let result = rewrite_figure("<figure>");
-    let actual = format!("{:?}", result.unwrap_err());
+    let result = rewrite_figure(
+        "<figure>",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    );
+    let actual = result.unwrap_err().to_string();
     assert_eq!(actual, "Unclosed `<figure>`");
 }
 
@@ -43,8 +58,11 @@ fn empty_caption() {
         "<figure>
 <figcaption></figcaption>
 </figure>",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
-    let actual = format!("{:?}", result.unwrap_err());
+    let actual = result.unwrap_err().to_string();
     assert_eq!(actual, "Missing caption in `<figcaption>`");
 }
 
@@ -54,7 +72,517 @@ fn unclosed_caption() {
         "<figure>
 <figcaption>
 </figure>",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
-    let actual = format!("{:?}", result.unwrap_err());
+    let actual = result.unwrap_err().to_string();
     assert_eq!(actual, "Unclosed `<figcaption>`");
 }
+
+#[test]
+fn errors_have_source_offsets() {
+    let text = "Some text.
+
+<figure>
+
+<figcaption>
+</figure>";
+    let error = rewrite_figure(
+        text,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.offset, text.find("<figcaption>").unwrap());
+
+    let text = "Some text.
+
+<figure>";
+    let error = rewrite_figure(
+        text,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.offset, text.find("<figure>").unwrap());
+}
+
+#[test]
+fn figure_with_attributes() {
+    let actual = rewrite_figure(
+        r#"<figure id="figure-17-1" class="wide">
+
+<img src="img/trpl17-01.svg" class="center" alt="A diagram of two tasks" />
+
+<figcaption class="caption">Figure 17-1: A concurrent workflow</figcaption>
+
+</figure>"#,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap();
+
+    let expected = r#"
+
+<img src="img/trpl17-01.svg" class="center" alt="A diagram of two tasks" />
+
+Figure 17-1: A concurrent workflow
+
+"#;
+
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn multi_line_caption_with_attributes() {
+    let actual = rewrite_figure(
+        r#"<figure>
+
+<img src="img/trpl17-01.svg" alt="A diagram">
+
+<figcaption class="caption">
+
+Figure 17-1: A *concurrent* workflow
+
+</figcaption>
+
+</figure>"#,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap();
+
+    assert!(!actual.contains("figcaption"), "{actual}");
+    assert!(actual.contains("Figure 17-1: A *concurrent* workflow"));
+    assert!(actual.contains(r#"alt="A diagram""#));
+}
+
+#[test]
+fn content_after_opening_tag() {
+    let actual = rewrite_figure(
+        r#"<figure id="figure-1"><img src="img/example.svg" alt="An example">
+<figcaption>Figure 1: An example</figcaption>
+</figure>"#,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap();
+
+    assert!(!actual.contains("<figure"), "{actual}");
+    assert!(actual.contains(r#"<img src="img/example.svg" alt="An example">"#));
+    assert!(actual.contains("Figure 1: An example"));
+}
+
+#[test]
+fn other_tags_are_ignored() {
+    let text = "<figures>\n\n<figcaptions>\n";
+    let actual = rewrite_figure(
+        text,
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap();
+    assert_eq!(actual, text);
+}
+
+#[test]
+fn close_without_open() {
+    let actual = rewrite_figure(
+        "</figure>",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap_err()
+    .to_string();
+    assert_eq!(actual, "Closing `</figure>` while not in a `<figure>`.");
+
+    let actual = rewrite_figure(
+        "<figure>\n</figcaption>\n</figure>",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
+    )
+    .unwrap_err()
+    .to_string();
+    assert_eq!(
+        actual,
+        "Closing `</figcaption>` while not in a `<figcaption>`."
+    );
+}
+
+mod components {
+    use super::*;
+
+    const CHAPTER: &str = r#"Some text.
+
+<Figure number="4-1" src="img/trpl04-01.svg" alt="A box &amp; arrows" caption="A `String` in memory" />
+
+More text.
+"#;
+
+    #[test]
+    fn default_mode() {
+        let actual = rewrite_figure(
+            CHAPTER,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            actual,
+            r#"Some text.
+
+<figure id="figure-4-1">
+
+<img src="img/trpl04-01.svg" class="center" alt="A box &amp; arrows" />
+
+<figcaption>Figure 4-1: A <code>String</code> in memory</figcaption>
+
+</figure>
+
+More text."#
+        );
+    }
+
+    #[test]
+    fn simple_mode() {
+        let actual = rewrite_figure(
+            CHAPTER,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            actual,
+            r#"Some text.
+
+<img src="img/trpl04-01.svg" class="center" alt="A box &amp; arrows" />
+
+Figure 4-1: A `String` in memory
+
+More text."#
+        );
+    }
+
+    #[test]
+    fn localized_label() {
+        let labels = Labels {
+            figure: String::from("Figura"),
+            ..Labels::default()
+        };
+        let actual = rewrite_figure(
+            CHAPTER,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &labels,
+        )
+        .unwrap();
+        assert!(actual.contains("<figcaption>Figura 4-1: "), "{actual}");
+        let actual = rewrite_figure(
+            CHAPTER,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &labels,
+        )
+        .unwrap();
+        assert!(actual.contains("\nFigura 4-1: "), "{actual}");
+    }
+
+    #[test]
+    fn closed_on_the_same_line() {
+        let actual = rewrite_figure(
+            r#"<Figure number="4-1" src="img/trpl04-01.svg"></Figure>"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        )
+        .unwrap();
+        assert!(actual.contains(r#"<figure id="figure-4-1">"#), "{actual}");
+        assert!(actual.contains("<figcaption>Figure 4-1</figcaption>"));
+    }
+
+    #[test]
+    fn default_mode_leaves_html_figures_alone() {
+        let text = r#"<figure id="figure-17-1" class="wide">
+
+<img src="img/trpl17-01.svg" alt="A diagram">
+
+<figcaption class="caption">Figure 17-1: A concurrent workflow</figcaption>
+
+</figure>
+"#;
+        let actual = rewrite_figure(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        )
+        .unwrap();
+        assert_eq!(actual, text);
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let result = rewrite_figure(
+            r#"<Figure src="img/trpl04-01.svg" />"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing required attribute for `<Figure>`: 'number'"
+        );
+
+        let result = rewrite_figure(
+            r#"<Figure number="4-1" />"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing required attribute for `<Figure>`: 'src'"
+        );
+    }
+
+    #[test]
+    fn unsupported_attribute() {
+        let result = rewrite_figure(
+            r#"<Figure number="4-1" src="img/trpl04-01.svg" title="Nope" />"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unsupported attribute name: 'title'"
+        );
+    }
+
+    #[test]
+    fn closing_tag_on_its_own_line() {
+        let result = rewrite_figure(
+            "<Figure number=\"4-1\" src=\"img/trpl04-01.svg\">\n</Figure>\n",
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "A `<Figure>` has no content, so close it on the same line, or write it as `<Figure ... />`"
+        );
+    }
+}
+
+mod references {
+    use super::*;
+
+    fn index() -> FigureIndex {
+        let mut index = FigureIndex::default();
+        let errors = index.add_chapter(
+            r#"<Figure number="4-1" src="img/trpl04-01.svg" />"#,
+            Some(Path::new("ch04-01-what-is-ownership.md")),
+        );
+        assert!(errors.is_empty());
+        index
+    }
+
+    #[test]
+    fn default_mode() {
+        let actual = resolve_references(
+            r#"As shown in <FigureRef number="4-1"/>, ..."#,
+            &index(),
+            Some(Path::new("ch04-02-references-and-borrowing.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap();
+        assert_eq!(
+            actual,
+            r#"As shown in <a href="ch04-01-what-is-ownership.html#figure-4-1">Figure 4-1</a>, ..."#
+        );
+    }
+
+    #[test]
+    fn same_chapter() {
+        let actual = resolve_references(
+            r#"As shown in <FigureRef number="4-1"/>, ..."#,
+            &index(),
+            Some(Path::new("ch04-01-what-is-ownership.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap();
+        assert_eq!(
+            actual,
+            r##"As shown in <a href="#figure-4-1">Figure 4-1</a>, ..."##
+        );
+    }
+
+    #[test]
+    fn simple_mode() {
+        let actual = resolve_references(
+            r#"As shown in <FigureRef number="4-1"/>, ..."#,
+            &index(),
+            Some(Path::new("ch04-02-references-and-borrowing.md")),
+            Mode::Simple,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap();
+        assert_eq!(actual, "As shown in Figure 4-1, ...");
+    }
+
+    #[test]
+    fn nonexistent_figure() {
+        let errors = resolve_references(
+            r#"As shown in <FigureRef number="4-2"/>, ..."#,
+            &index(),
+            None,
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "`<FigureRef>` to nonexistent figure: '4-2'"
+        );
+    }
+
+    #[test]
+    fn several_in_one_html_block() {
+        let actual = resolve_references(
+            "<FigureRef number=\"4-1\"/>\nand <FigureRef number=\"4-1\"/>\n",
+            &index(),
+            Some(Path::new("ch04-02-references-and-borrowing.md")),
+            Mode::Simple,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap();
+        assert_eq!(actual, "Figure 4-1\nand Figure 4-1\n");
+    }
+
+    #[test]
+    fn nonexistent_figure_in_html_block() {
+        let errors = resolve_references(
+            "<FigureRef number=\"4-1\"/>\nand <FigureRef number=\"4-2\"/>\n",
+            &index(),
+            None,
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved)
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "`<FigureRef>` to nonexistent figure: '4-2'"
+        );
+        assert_eq!(errors[0].offset, "<FigureRef number=\"4-1\"/>\nand ".len());
+    }
+
+    #[test]
+    fn duplicate_number() {
+        let mut index = index();
+        let errors = index.add_chapter(
+            "Text.\n\n<Figure number=\"4-1\" src=\"img/other.svg\" />\n",
+            Some(Path::new("ch04-02-references-and-borrowing.md")),
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Duplicate figure number: '4-1'");
+        assert_eq!(errors[0].offset, "Text.\n\n".len());
+    }
+}
+
+mod alt_text {
+    use super::*;
+
+    fn messages(src: &str) -> Vec<(usize, String)> {
+        missing_alt_text(src)
+            .into_iter()
+            .map(|error| (error.offset, error.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn images_with_alt_text() {
+        let src = r#"![A crab](img/ferris.svg)
+
+<img src="img/trpl04-01.svg" alt="A string in memory" />
+
+Inline <img src="img/ferris.svg" alt="Ferris"> image.
+
+<Figure number="4-1" src="img/trpl04-01.svg" alt="A string in memory" />
+
+<imgur-embed></imgur-embed>
+
+`<img src="in-code.svg">`
+"#;
+        assert_eq!(messages(src), vec![]);
+    }
+
+    #[test]
+    fn markdown_images() {
+        let src = "![](img/ferris.svg)\n\nText ![ ](img/ferris.svg).\n";
+        assert_eq!(
+            messages(src),
+            vec![
+                (0, String::from("Image without alt text")),
+                (
+                    src.find("![ ]").unwrap(),
+                    String::from("Image without alt text")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn img_tags() {
+        let src = r#"<figure>
+
+<img src="img/trpl04-01.svg" class="center" />
+
+</figure>
+
+Inline <img src="img/ferris.svg" alt=""> image.
+"#;
+        assert_eq!(
+            messages(src),
+            vec![
+                (
+                    src.find("<img").unwrap(),
+                    String::from("`<img>` without alt text")
+                ),
+                (
+                    src.rfind("<img").unwrap(),
+                    String::from("`<img>` without alt text")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn figure_components() {
+        let src = "<Figure number=\"4-1\" src=\"img/trpl04-01.svg\" />\n";
+        assert_eq!(
+            messages(src),
+            vec![(0, String::from("`<Figure>` without alt text"))]
+        );
+    }
+}
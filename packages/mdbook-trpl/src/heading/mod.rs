@@ -1,14 +1,81 @@
-use anyhow::anyhow;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
 use mdbook::{
-    book::Book,
+    book::{Book, Chapter, SectionNumber},
     preprocess::{Preprocessor, PreprocessorContext},
+    utils::unique_id_from_content,
     BookItem,
 };
-use pulldown_cmark::{Event, Tag, TagEnd};
-use pulldown_cmark_to_cmark::cmark;
+use pulldown_cmark::{Event, HeadingLevel, Tag, TagEnd};
+use serde_json::json;
 
-use crate::{CompositeError, Mode};
+use crate::{
+    config::{Config, PinnedId, Rewrite},
+    diagnostic::{
+        chapter_path, Diagnostic, Diagnostics, ErrorKind, SourceError,
+    },
+    Mode, Spanned,
+};
 
+/// A preprocessor for headings.
+///
+/// In the simple mode, it strips inline markup like emphasis and code from
+/// headings, leaving only their text. It also drops any attributes, like IDs,
+/// since the print version has no use for them.
+///
+/// A heading can pin its ID, so that links to it keep working when its text
+/// changes, for example in a translation:
+///
+/// ```markdown
+/// ## Regole di Ownership {#ownership-rules}
+/// ```
+///
+/// By default, the pinned ID is simply the heading's `id`. With
+/// `pinned-id = "anchor"` in the configuration, the heading instead keeps the
+/// `id` generated from its text, and the pinned ID becomes an additional anchor
+/// right before it, so both the old and the new links work:
+///
+/// ```markdown
+/// <div id="ownership-rules"></div>
+///
+/// ## Regole di Ownership
+/// ```
+///
+/// In the default mode, it can also number the headings and write out a table
+/// of contents as JSON, for tools like a search index to use:
+///
+/// ```toml
+/// [preprocessor.trpl-heading]
+/// numbering = true
+/// toc = "toc.json"
+/// ```
+///
+/// With `numbering`, the headings in a numbered chapter start with their section
+/// number, written the same way as in `mdbook`'s sidebar, so the second `##`
+/// heading in chapter 4.1 becomes `## 4.1.2. ...`. In a chapter with
+/// sub-chapters of its own, like an introduction to them, only the `#` heading
+/// is numbered, since the numbers after the chapter's belong to the
+/// sub-chapters. Each numbered heading gets an explicit ID, so adding the
+/// number does not change the ID `mdbook` generates for it. The table of contents has an entry
+/// like this for every heading, in the order of the book:
+///
+/// ```json
+/// {
+///   "level": 2,
+///   "text": "The Stack and the Heap",
+///   "slug": "the-stack-and-the-heap",
+///   "chapter": "ch04-01-what-is-ownership.md"
+/// }
+/// ```
+///
+/// The `toc` path is relative to the book's root. Renderers may empty their
+/// output directory before writing to it, so put it next to that directory
+/// rather than in it.
 pub struct TrplHeading;
 
 impl Preprocessor for TrplHeading {
@@ -21,23 +88,62 @@ impl Preprocessor for TrplHeading {
         ctx: &PreprocessorContext,
         mut book: Book,
     ) -> anyhow::Result<Book> {
-        let mode = Mode::from_context(ctx, self.name())?;
+        let Config {
+            mode,
+            rewrite,
+            heading: config,
+            ..
+        } = Config::from_context(ctx, self.name())?;
+        let outlines = mode == Mode::Default && config.outlines();
 
         let mut errors = vec![];
+        let mut toc = HashMap::new();
         book.for_each_mut(|item| {
             if let BookItem::Chapter(ref mut chapter) = item {
-                match rewrite_headings(&chapter.content, mode) {
-                    Ok(rewritten) => chapter.content = rewritten,
-                    Err(reason) => errors.push(reason),
+                let rewritten = rewrite_headings(
+                    &chapter.content,
+                    mode,
+                    rewrite,
+                    config.pinned_id,
+                );
+                let outlined = rewritten.and_then(|rewritten| {
+                    if outlines {
+                        outline(&rewritten, chapter, config.numbering, rewrite)
+                            .map(|(rewritten, entries)| {
+                                (rewritten, Some(entries))
+                            })
+                    } else {
+                        Ok((rewritten, None))
+                    }
+                });
+                match outlined {
+                    Ok((rewritten, entries)) => {
+                        chapter.content = rewritten;
+                        if let Some(entries) = entries {
+                            toc.insert(
+                                chapter.path.clone().unwrap_or_default(),
+                                entries,
+                            );
+                        }
+                    }
+                    Err(reason) => errors.push(Diagnostic::new(
+                        chapter_path(&ctx.config.book.src, chapter),
+                        &chapter.content,
+                        reason,
+                    )),
                 }
             }
         });
 
-        if errors.is_empty() {
-            Ok(book)
-        } else {
-            Err(CompositeError(errors).into())
+        if !errors.is_empty() {
+            return Err(Diagnostics(errors).into());
         }
+
+        if let (Some(path), true) = (&config.toc, outlines) {
+            write_toc(&ctx.root.join(path), &book, toc)?;
+        }
+
+        Ok(book)
     }
 
     fn supports_renderer(&self, renderer: &str) -> bool {
@@ -45,21 +151,271 @@ impl Preprocessor for TrplHeading {
     }
 }
 
-fn rewrite_headings(src: &str, mode: Mode) -> anyhow::Result<String> {
-    // Don't rewrite anything for the default mode.
-    if mode == Mode::Default {
-        return Ok(src.into());
+fn rewrite_headings(
+    src: &str,
+    mode: Mode,
+    rewrite: Rewrite,
+    pinned_id: PinnedId,
+) -> Result<String, SourceError> {
+    let events = crate::parser(src).into_offset_iter();
+    let events = match (mode, pinned_id) {
+        // Don't rewrite anything for the default mode, unless pinned IDs need
+        // to become anchors.
+        (Mode::Default, PinnedId::Id) => return Ok(src.into()),
+        (Mode::Default, PinnedId::Anchor) => anchor_pinned_ids(events),
+        (Mode::Simple, _) => simplify_headings(events)?,
+    };
+    crate::to_markdown(src, events, rewrite)
+}
+
+/// Replace each pinned heading ID with an anchor right before the heading.
+pub(crate) fn anchor_pinned_ids<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+) -> Vec<Spanned<'e>> {
+    events
+        .into_iter()
+        .flat_map(|(event, range)| match event {
+            Event::Start(Tag::Heading {
+                level,
+                id: Some(id),
+                classes,
+                attrs,
+            }) => {
+                // As with notes, the `SoftBreak`s keep the anchor from being
+                // rendered as part of whatever block comes before it.
+                let anchor = format!(r#"<div id="{id}"></div>"#);
+                let heading = Tag::Heading {
+                    level,
+                    id: None,
+                    classes,
+                    attrs,
+                };
+                [
+                    Event::SoftBreak,
+                    Event::SoftBreak,
+                    Event::Html(anchor.into()),
+                    Event::SoftBreak,
+                    Event::SoftBreak,
+                    Event::Start(heading),
+                ]
+                .map(|event| (event, range.clone()))
+                .to_vec()
+            }
+            event => vec![(event, range)],
+        })
+        .collect()
+}
+
+/// Number and list the headings in `src`, the Markdown for `chapter`.
+fn outline(
+    src: &str,
+    chapter: &Chapter,
+    numbering: bool,
+    rewrite: Rewrite,
+) -> Result<(String, Vec<TocEntry>), SourceError> {
+    let events = crate::parser(src).into_offset_iter();
+    let (events, entries) = outline_headings(events, chapter, numbering);
+    let rewritten = crate::to_markdown(src, events, rewrite)?;
+    Ok((rewritten, entries))
+}
+
+/// An entry in the JSON table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TocEntry {
+    pub(crate) level: usize,
+    pub(crate) text: String,
+    pub(crate) slug: String,
+    pub(crate) chapter: PathBuf,
+}
+
+/// Number the headings in a chapter, if `numbering`, and list them all for the
+/// table of contents.
+///
+/// A `# ` heading gets the chapter's own number, and the others count up from
+/// there, so the second `## ` heading in chapter 4.1 is 4.1.2. Headings in a
+/// chapter without a number are not numbered, and neither are the headings
+/// below the `# ` one in a chapter with sub-chapters.
+pub(crate) fn outline_headings<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+    chapter: &Chapter,
+    numbering: bool,
+) -> (Vec<Spanned<'e>>, Vec<TocEntry>) {
+    let path = chapter.path.as_deref().unwrap_or(Path::new(""));
+    let has_sub_chapters = chapter
+        .sub_items
+        .iter()
+        .any(|item| matches!(item, BookItem::Chapter(_)));
+    let mut output = vec![];
+    let mut entries = vec![];
+    // Generating the slugs the same way `mdbook` generates IDs, with the same
+    // suffixes for duplicates, makes them match.
+    let mut ids = HashMap::new();
+    let mut counts = [0; 5];
+    let mut heading: Option<(Spanned, Vec<Spanned>)> = None;
+
+    for (event, range) in events {
+        match (event, &mut heading) {
+            (start @ Event::Start(Tag::Heading { .. }), None) => {
+                heading = Some(((start, range), vec![]));
+            }
+
+            (end @ Event::End(TagEnd::Heading(_)), Some(_)) => {
+                let Some((
+                    (
+                        Event::Start(Tag::Heading {
+                            level,
+                            id,
+                            classes,
+                            attrs,
+                        }),
+                        start,
+                    ),
+                    content,
+                )) = heading.take()
+                else {
+                    unreachable!("only the start of a heading is stored");
+                };
+
+                let slug = match &id {
+                    Some(id) => id.to_string(),
+                    None => {
+                        let mut html = String::new();
+                        pulldown_cmark::html::push_html(
+                            &mut html,
+                            content.iter().map(|(event, _)| event.clone()),
+                        );
+                        unique_id_from_content(&html, &mut ids)
+                    }
+                };
+
+                let section = chapter
+                    .number
+                    .as_ref()
+                    .filter(|_| numbering)
+                    .filter(|_| level == HeadingLevel::H1 || !has_sub_chapters)
+                    .map(|number| section_number(number, level, &mut counts));
+
+                // Keep the ID from changing to include the number.
+                let id = match section {
+                    Some(_) => Some(slug.clone().into()),
+                    None => id,
+                };
+                let heading = Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                };
+
+                entries.push(TocEntry {
+                    level: level as usize,
+                    text: plain_text(&content),
+                    slug,
+                    chapter: path.to_path_buf(),
+                });
+
+                output.push((Event::Start(heading), start.clone()));
+                if let Some(section) = section {
+                    output.push((
+                        Event::Text(format!("{section} ").into()),
+                        start,
+                    ));
+                }
+                output.extend(content);
+                output.push((end, range));
+            }
+
+            (event, Some((_, content))) => content.push((event, range)),
+            (event, None) => output.push((event, range)),
+        }
+    }
+
+    (output, entries)
+}
+
+/// The section number for the next heading at `level`, like "4.1.2.".
+fn section_number(
+    chapter: &SectionNumber,
+    level: HeadingLevel,
+    counts: &mut [u32; 5],
+) -> String {
+    // `# ` headings are the chapter itself, so `## ` headings are the first
+    // level to count.
+    let depth = level as usize - 1;
+    if depth > 0 {
+        counts[depth - 1] += 1;
+        counts[depth..].fill(0);
     }
 
+    let number = chapter.iter().chain(&counts[..depth]).copied().collect();
+    SectionNumber(number).to_string()
+}
+
+/// The text of a heading, without any markup.
+fn plain_text(content: &[Spanned]) -> String {
+    content
+        .iter()
+        .filter_map(|(event, _)| match event {
+            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+            Event::SoftBreak | Event::HardBreak => Some(" "),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Write the table of contents to `path` as JSON, in the order of the `book`'s
+/// chapters.
+pub(crate) fn write_toc(
+    path: &Path,
+    book: &Book,
+    mut toc: HashMap<PathBuf, Vec<TocEntry>>,
+) -> anyhow::Result<()> {
+    let entries = book
+        .iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => chapter.path.as_ref(),
+            _ => None,
+        })
+        .filter_map(|chapter| toc.remove(chapter))
+        .flatten()
+        .map(|entry| {
+            json!({
+                "level": entry.level,
+                "text": entry.text,
+                "slug": entry.slug,
+                "chapter": entry.chapter,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let write = || -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut json = serde_json::to_string_pretty(&entries)?;
+        json.push('\n');
+        fs::write(path, json)?;
+        Ok(())
+    };
+    write().with_context(|| {
+        format!("Could not write table of contents to {}", path.display())
+    })
+}
+
+/// Strip the inline markup and attributes from headings, leaving their text.
+pub(crate) fn simplify_headings<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+) -> Result<Vec<Spanned<'e>>, SourceError> {
     #[derive(Default)]
     struct State<'e> {
         in_heading: bool,
-        events: Vec<Event<'e>>,
+        heading_start: usize,
+        events: Vec<Spanned<'e>>,
     }
 
-    let final_state: State = crate::parser(src).try_fold(
+    let final_state: State = events.into_iter().try_fold(
         State::default(),
-        |mut state, event| -> anyhow::Result<State> {
+        |mut state, (event, range)| -> Result<State, SourceError> {
             if state.in_heading {
                 match event {
                     // When we see the start or end of any of the inline tags
@@ -79,22 +435,29 @@ fn rewrite_headings(src: &str, mode: Mode) -> anyhow::Result<String> {
                     // For code, we just emit the body of the inline code block,
                     // unchanged (the wrapping backticks are not present here).
                     Event::Code(code) => {
-                        state.events.push(Event::Text(code));
+                        state.events.push((Event::Text(code), range));
                     }
 
                     // Assume headings are well-formed; you cannot have a nested
                     // headings, so we don't have to check heading level.
                     Event::End(TagEnd::Heading(_)) => {
                         state.in_heading = false;
-                        state.events.push(event);
+                        state.events.push((event, range));
                     }
-                    _ => state.events.push(event),
+                    _ => state.events.push((event, range)),
                 }
-            } else if matches!(event, Event::Start(Tag::Heading { .. })) {
-                state.events.push(event);
+            } else if let Event::Start(Tag::Heading { level, .. }) = event {
+                state.heading_start = range.start;
+                let heading = Tag::Heading {
+                    level,
+                    id: None,
+                    classes: vec![],
+                    attrs: vec![],
+                };
+                state.events.push((Event::Start(heading), range));
                 state.in_heading = true;
             } else {
-                state.events.push(event);
+                state.events.push((event, range));
             }
 
             Ok(state)
@@ -102,12 +465,13 @@ fn rewrite_headings(src: &str, mode: Mode) -> anyhow::Result<String> {
     )?;
 
     if final_state.in_heading {
-        return Err(anyhow!("Unclosed heading"));
+        return Err(SourceError::new(
+            final_state.heading_start,
+            ErrorKind::UnclosedHeading,
+        ));
     }
 
-    let mut rewritten = String::new();
-    cmark(final_state.events.into_iter(), &mut rewritten)?;
-    Ok(rewritten)
+    Ok(final_state.events)
 }
 
 #[cfg(test)]
@@ -1,4 +1,5 @@
 use super::*;
+use crate::config::Rewrite;
 
 #[test]
 fn default_mode_is_unchanged() {
@@ -11,6 +12,8 @@ fn default_mode_is_unchanged() {
 ###### Last *heading* **level** with `code`
 ",
         Mode::Default,
+        Rewrite::Reserialize,
+        PinnedId::Id,
     );
 
     assert_eq!(
@@ -42,6 +45,8 @@ mod simple_mode {
 ###### Last *heading* _level_ test
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
@@ -53,6 +58,8 @@ mod simple_mode {
 ###### Last heading level test
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap());
@@ -63,6 +70,8 @@ mod simple_mode {
         let result = rewrite_headings(
             "# *This _is *extra* emphatic_ emphasis*.",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
         let expected = "# This is extra emphatic emphasis.";
 
@@ -80,6 +89,8 @@ mod simple_mode {
 ###### Last **heading** __level__ test
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
@@ -91,6 +102,8 @@ mod simple_mode {
 ###### Last heading level test
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap());
@@ -101,6 +114,8 @@ mod simple_mode {
         let result = rewrite_headings(
             "# **This __is **extra** emphatic__ emphasis**.",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
         let expected = "# This is extra emphatic emphasis.";
 
@@ -118,6 +133,8 @@ mod simple_mode {
 ###### Last `heading`
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
@@ -129,6 +146,8 @@ mod simple_mode {
 ###### Last heading
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap());
@@ -145,6 +164,8 @@ mod simple_mode {
 ###### Last <span>heading</span>
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
@@ -156,6 +177,8 @@ mod simple_mode {
 ###### Last heading
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap());
@@ -172,6 +195,8 @@ mod simple_mode {
 ###### Last ~~heading~~
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
@@ -183,6 +208,8 @@ mod simple_mode {
 ###### Last heading
 ",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap());
@@ -193,13 +220,194 @@ mod simple_mode {
         let result = rewrite_headings(
             "# **Nested ~~strikethrough _emphasis_ fun~~ times**",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         let expected = rewrite_headings(
             "# Nested strikethrough emphasis fun times",
             Mode::Simple,
+            Rewrite::Reserialize,
+            PinnedId::Id,
         );
 
         assert_eq!(result.unwrap(), expected.unwrap())
     }
 }
+
+#[test]
+fn default_mode_keeps_pinned_ids() {
+    let text = "## Regole di Ownership {#ownership-rules}\n";
+    let result = rewrite_headings(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        PinnedId::Id,
+    );
+    assert_eq!(result.unwrap(), text);
+}
+
+#[test]
+fn default_mode_turns_pinned_ids_into_anchors() {
+    let result = rewrite_headings(
+        "Some text.\n\n## Regole di Ownership {#ownership-rules}\n\nMore text.\n",
+        Mode::Default, Rewrite::Reserialize,
+        PinnedId::Anchor,
+    )
+    .unwrap();
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, crate::parser(&result));
+    assert_eq!(
+        html,
+        "<p>Some text.</p>\n<div id=\"ownership-rules\"></div>\n<h2>Regole di Ownership</h2>\n<p>More text.</p>\n"
+    );
+}
+
+#[test]
+fn anchors_only_for_pinned_ids() {
+    let text = "## Regole di Ownership\n";
+    let result = rewrite_headings(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        PinnedId::Anchor,
+    );
+    assert_eq!(result.unwrap(), "## Regole di Ownership");
+}
+
+#[test]
+fn simple_mode_strips_pinned_ids() {
+    let result = rewrite_headings(
+        "## Regole di *Ownership* {#ownership-rules .rules}\n",
+        Mode::Simple,
+        Rewrite::Reserialize,
+        PinnedId::Id,
+    );
+    assert_eq!(result.unwrap(), "## Regole di Ownership");
+}
+
+mod outline {
+    use super::*;
+
+    const CHAPTER: &str = "# What Is Ownership?
+
+## Ownership Rules
+
+## The Stack and the Heap
+
+### The `String` Type
+
+### The `String` Type
+
+## Rules {#ownership-rules}
+";
+
+    fn chapter(number: Option<Vec<u32>>) -> Chapter {
+        Chapter {
+            number: number.map(SectionNumber),
+            ..Chapter::new(
+                "What Is Ownership?",
+                String::from(CHAPTER),
+                "ch04-01-what-is-ownership.md",
+                vec![],
+            )
+        }
+    }
+
+    fn outline_chapter(
+        number: Option<Vec<u32>>,
+        numbering: bool,
+    ) -> (String, Vec<TocEntry>) {
+        outline(CHAPTER, &chapter(number), numbering, Rewrite::Reserialize)
+            .unwrap()
+    }
+
+    fn render(markdown: &str) -> String {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, crate::parser(markdown));
+        html
+    }
+
+    #[test]
+    fn numbers_headings() {
+        let (rewritten, _) = outline_chapter(Some(vec![4, 1]), true);
+        assert_eq!(
+            render(&rewritten),
+            r#"<h1 id="what-is-ownership">4.1. What Is Ownership?</h1>
+<h2 id="ownership-rules">4.1.1. Ownership Rules</h2>
+<h2 id="the-stack-and-the-heap">4.1.2. The Stack and the Heap</h2>
+<h3 id="the-string-type">4.1.2.1. The <code>String</code> Type</h3>
+<h3 id="the-string-type-1">4.1.2.2. The <code>String</code> Type</h3>
+<h2 id="ownership-rules">4.1.3. Rules</h2>
+"#
+        );
+    }
+
+    #[test]
+    fn numbers_top_level_chapter() {
+        let (rewritten, _) = outline_chapter(Some(vec![4]), true);
+        let rendered = render(&rewritten);
+        assert!(
+            rendered.starts_with(
+                r#"<h1 id="what-is-ownership">4. What Is Ownership?</h1>
+<h2 id="ownership-rules">4.1. Ownership Rules</h2>"#
+            ),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn chapter_with_sub_chapters() {
+        let sub_chapter = Chapter {
+            number: Some(SectionNumber(vec![4, 1])),
+            ..Chapter::new("Sub-chapter", String::new(), "ch04-01.md", vec![])
+        };
+        let mut chapter = chapter(Some(vec![4]));
+        chapter.sub_items.push(BookItem::Chapter(sub_chapter));
+        let (rewritten, entries) =
+            outline(CHAPTER, &chapter, true, Rewrite::Reserialize).unwrap();
+        assert_eq!(
+            render(&rewritten),
+            render(CHAPTER).replace(
+                "<h1>What Is Ownership?</h1>",
+                r#"<h1 id="what-is-ownership">4. What Is Ownership?</h1>"#
+            )
+        );
+        assert_eq!(entries.len(), 6);
+    }
+
+    #[test]
+    fn unnumbered_chapter() {
+        let (rewritten, _) = outline_chapter(None, true);
+        assert_eq!(render(&rewritten), render(CHAPTER));
+    }
+
+    #[test]
+    fn without_numbering() {
+        let (rewritten, _) = outline_chapter(Some(vec![4, 1]), false);
+        assert_eq!(render(&rewritten), render(CHAPTER));
+    }
+
+    #[test]
+    fn lists_headings() {
+        let (_, entries) = outline_chapter(Some(vec![4, 1]), true);
+        let entry = |level, text: &str, slug: &str| TocEntry {
+            level,
+            text: text.into(),
+            slug: slug.into(),
+            chapter: PathBuf::from("ch04-01-what-is-ownership.md"),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry(1, "What Is Ownership?", "what-is-ownership"),
+                entry(2, "Ownership Rules", "ownership-rules"),
+                entry(2, "The Stack and the Heap", "the-stack-and-the-heap"),
+                entry(3, "The String Type", "the-string-type"),
+                entry(3, "The String Type", "the-string-type-1"),
+                entry(2, "Rules", "ownership-rules"),
+            ]
+        );
+    }
+}
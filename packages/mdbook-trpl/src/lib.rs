@@ -1,15 +1,29 @@
+mod combined;
 mod config;
+mod diagnostic;
 mod figure;
 mod heading;
 mod listing;
 mod note;
 
-pub use config::Mode;
+pub use combined::{transform, Trpl};
+pub use config::{
+    AltText, CalloutConfig, Config, HeadingConfig, Labels, Mode, NoteConfig,
+    PinnedId, Rewrite, Transforms,
+};
+pub use diagnostic::{
+    Diagnostic, Diagnostics, ErrorKind, Severity, DIAGNOSTICS_VAR,
+};
 pub use figure::TrplFigure as Figure;
 pub use heading::TrplHeading as Heading;
 pub use listing::TrplListing as Listing;
 pub use note::TrplNote as Note;
-use pulldown_cmark::{Options, Parser};
+use std::{collections::HashMap, mem, ops::Range};
+
+use pulldown_cmark::{Event, Options, Parser};
+use pulldown_cmark_to_cmark::{cmark, cmark_resume};
+
+use crate::diagnostic::SourceError;
 
 /// Convenience function to get a parser matching `mdbook::new_cmark_parser`.
 ///
@@ -36,15 +50,169 @@ pub fn parser(text: &str) -> Parser<'_> {
     Parser::new_ext(text, opts)
 }
 
-#[derive(Debug, thiserror::Error)]
-struct CompositeError(Vec<anyhow::Error>);
+/// An event along with the range of the source it came from, as produced by
+/// [`Parser::into_offset_iter`]. Events which replace others keep the range of
+/// the event they replace, so that errors can always point at the source.
+type Spanned<'e> = (Event<'e>, Range<usize>);
+
+/// Render Markdown which is all inline content, like a caption, to HTML which
+/// can go inside another element.
+fn inline_html(markdown: &str) -> String {
+    let mut buf = String::with_capacity(markdown.len() * 2);
+    pulldown_cmark::html::push_html(&mut buf, parser(markdown));
+
+    // This is not particularly principled, but since the only place it is used
+    // is for captions, it is “fine”.
+    buf.replace("<p>", "").replace("</p>", "").replace('\n', "")
+}
+
+/// Serialize the events back to Markdown. The events' ranges must refer to
+/// `src`, the text they were parsed from.
+fn to_markdown<'e>(
+    src: &str,
+    events: impl IntoIterator<Item = Spanned<'e>>,
+    rewrite: Rewrite,
+) -> Result<String, SourceError> {
+    match rewrite {
+        Rewrite::Reserialize => serialize(events, src.len() * 2),
+        Rewrite::Splice => splice(src, events),
+    }
+}
+
+fn serialize<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+    capacity: usize,
+) -> Result<String, SourceError> {
+    let mut buf = String::with_capacity(capacity);
+    cmark(events.into_iter().map(|(event, _)| event), &mut buf)
+        .map_err(|e| SourceError::new(0, ErrorKind::Markdown(e)))?;
+    Ok(buf)
+}
+
+/// Copy the source of each top-level block which is the same as when parsing
+/// `src`, and serialize only the ones which changed, in place of the source
+/// they replace.
+///
+/// Changed blocks whose ranges overlap are serialized together, since the
+/// events which replace a single block, like a note, all fall within its range.
+/// Whatever is between the blocks, like blank lines and link reference
+/// definitions, is always copied.
+fn splice<'e>(
+    src: &str,
+    events: impl IntoIterator<Item = Spanned<'e>>,
+) -> Result<String, SourceError> {
+    let original = blocks(parser(src).into_offset_iter());
+    let spans: Vec<_> = original.iter().map(|block| span(block)).collect();
+    let original: HashMap<_, _> = original
+        .iter()
+        .map(|block| (block[0].1.clone(), block))
+        .collect();
+
+    // Replacements cover the whole of any block they overlap in the source,
+    // rather than starting partway through one, like after a blockquote's `>`.
+    let widen = |range: Range<usize>| {
+        let end = range.end.max(range.start + 1);
+        spans
+            .iter()
+            .filter(|block| block.start < end && range.start < block.end)
+            .fold(range.clone(), |widened, block| {
+                widened.start.min(block.start)..widened.end.max(block.end)
+            })
+    };
+
+    let mut buf = String::with_capacity(src.len());
+    let mut cursor = 0;
+    let mut changed: Vec<Spanned> = vec![];
+    let mut changed_span = 0..0;
+
+    for block in blocks(events) {
+        let span = widen(span(&block));
+        let overlaps = !changed.is_empty() && span.start < changed_span.end;
+        let unchanged = original.get(&block[0].1) == Some(&&block);
+
+        if !overlaps && !changed.is_empty() {
+            let run = mem::take(&mut changed);
+            cursor = replace(&mut buf, src, cursor, &changed_span, run)?;
+        }
+
+        if unchanged && !overlaps && span.start >= cursor {
+            buf.push_str(&src[cursor..span.end]);
+            cursor = span.end;
+        } else if changed.is_empty() {
+            changed_span = span;
+            changed = block;
+        } else {
+            changed_span.start = changed_span.start.min(span.start);
+            changed_span.end = changed_span.end.max(span.end);
+            changed.extend(block);
+        }
+    }
+
+    if !changed.is_empty() {
+        cursor = replace(&mut buf, src, cursor, &changed_span, changed)?;
+    }
+    buf.push_str(&src[cursor..]);
+    Ok(buf)
+}
+
+/// Split the events into top-level blocks: a `Start` through its matching
+/// `End`, or a single event outside of any.
+fn blocks<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+) -> Vec<Vec<Spanned<'e>>> {
+    let mut blocks = vec![];
+    let mut block = vec![];
+    let mut depth = 0usize;
+    for (event, range) in events {
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        block.push((event, range));
+        if depth == 0 {
+            blocks.push(mem::take(&mut block));
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// The part of the source which a block's events cover.
+fn span(block: &[Spanned]) -> Range<usize> {
+    let start = block.iter().map(|(_, range)| range.start).min();
+    let end = block.iter().map(|(_, range)| range.end).max();
+    start.unwrap_or(0)..end.unwrap_or(0)
+}
+
+/// Write everything from `cursor` up to `span`, then the serialized `events` in
+/// place of `span`, and return where to carry on copying from.
+fn replace(
+    buf: &mut String,
+    src: &str,
+    cursor: usize,
+    span: &Range<usize>,
+    events: Vec<Spanned>,
+) -> Result<usize, SourceError> {
+    let start = span.start.max(cursor);
+    let end = span.end.max(start);
+    buf.push_str(&src[cursor..start]);
 
-impl std::fmt::Display for CompositeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Error(s) rewriting input: {}",
-            self.0.iter().map(|e| format!("{e:?}")).collect::<String>()
-        )
+    // The blank lines around the block are already in the source, so only the
+    // newline ending the block itself is kept. So are the definitions for any
+    // reference links, which is why this does not `finalize` the state.
+    let mut serialized = String::with_capacity(end - start);
+    cmark_resume(
+        events.into_iter().map(|(event, _)| event),
+        &mut serialized,
+        None,
+    )
+    .map_err(|e| SourceError::new(start, ErrorKind::Markdown(e)))?;
+    buf.push_str(serialized.trim_matches('\n'));
+    if src[start..end].ends_with('\n') {
+        buf.push('\n');
     }
+    Ok(end)
 }
@@ -1,4 +1,10 @@
-use anyhow::anyhow;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+    ops::{Range, RangeInclusive},
+    path::{Component, Path, PathBuf},
+};
+
 use html_parser::Dom;
 use mdbook::{
     book::Book,
@@ -6,10 +12,16 @@ use mdbook::{
     preprocess::{Preprocessor, PreprocessorContext},
     BookItem,
 };
-use pulldown_cmark::{html, Event};
-use pulldown_cmark_to_cmark::cmark;
+use pulldown_cmark::{Event, Tag, TagEnd};
 
-use crate::{config::Mode, CompositeError};
+use crate::{
+    config::{Config, Labels, Mode, Rewrite},
+    diagnostic::{
+        chapter_path, Diagnostic, Diagnostics, ErrorKind, SourceError,
+        SourceMap,
+    },
+    Spanned,
+};
 
 /// A preprocessor for rendering listings more elegantly.
 ///
@@ -45,7 +57,7 @@ use crate::{config::Mode, CompositeError};
 /// When `output-mode = "simple"` in the configuration, it instead emits:
 ///
 /// ````markdown
-/// Filename: src/main.rs
+/// src/main.rs
 ///
 /// ```rust
 /// fn main() {}
@@ -53,6 +65,96 @@ use crate::{config::Mode, CompositeError};
 ///
 /// Listing 1-2: Some *text*, yeah?
 /// ````
+///
+/// The "Listing" and "Filename:" labels can be localized with a `labels` table
+/// in the configuration:
+///
+/// ```toml
+/// [preprocessor.trpl-listing.labels]
+/// listing = "Codice"
+/// file-name = "File:"
+/// ```
+///
+/// Both modes use the same labels, except that the simple mode only puts a
+/// label before the file name when `file-name` is set.
+///
+/// Listings can be referenced from anywhere in the book with a `<ListingRef>`:
+///
+/// ```markdown
+/// As you can see in <ListingRef number="1-2"/>, ...
+/// ```
+///
+/// In the default mode, this becomes a link to the listing, which works across
+/// chapters:
+///
+/// ```markdown
+/// As you can see in <a href="ch01-02-hello-world.html#listing-1-2">Listing 1-2</a>, ...
+/// ```
+///
+/// In the simple mode, it is just the text, "Listing 1-2". Referencing a number
+/// which no `<Listing>` in the book defines is an error.
+///
+/// Instead of typing the number by hand, a listing can use `number="auto"`,
+/// in which case it is numbered `<chapter>-<n>`, where `<chapter>` is the
+/// number of the top-level chapter it is in and `<n>` counts the numbered
+/// listings in that chapter (including its sections) in document order. Since
+/// the number is not known up front, give such a listing a `name` to reference
+/// it by:
+///
+/// ```markdown
+/// <Listing number="auto" name="hello-world" caption="Hello, world!">
+///
+/// ...
+///
+/// </Listing>
+///
+/// As you can see in <ListingRef name="hello-world"/>, ...
+/// ```
+///
+/// Both the caption and the reference use the assigned number.
+///
+/// Lines of a listing's code can be called out with `highlight` and `dim`,
+/// each of which takes a comma-separated list of lines and inclusive ranges of
+/// lines, counting from 1:
+///
+/// ````markdown
+/// <Listing number="1-3" highlight="3-5,9" dim="1-2">
+///
+/// ```rust
+/// ...
+/// ```
+///
+/// </Listing>
+/// ````
+///
+/// In the default mode, the code block is left as is, so that `mdbook test`
+/// still tests it and hidden lines stay hidden, and the lines are listed on the
+/// `<figure>` instead:
+///
+/// ```markdown
+/// <figure class="listing" id="listing-1-3" data-highlight="3-5, 9" data-dim="1-2">
+/// ```
+///
+/// The book's `theme/listing.js` then wraps each of those lines in a
+/// `<span class="highlight-line">` or `<span class="dim-line">`. Lines are
+/// counted as written in the source, so hidden lines count, and a listing with
+/// more than one code block numbers its lines continuously across them. In the
+/// simple mode, the code is left as is too, and the lines are listed in text
+/// right after the file name, so they survive into the print version:
+///
+/// ````markdown
+/// Highlighted lines: 3-5, 9
+/// Dimmed lines: 1-2
+///
+/// ```rust
+/// ...
+/// ```
+/// ````
+///
+/// Those labels can be localized like the others, with `highlighted-lines` and
+/// `dimmed-lines` in the `labels` table. Malformed ranges, lines which are both
+/// highlighted and dimmed, and lines past the end of the listing's code are all
+/// errors.
 pub struct TrplListing;
 
 impl Preprocessor for TrplListing {
@@ -61,14 +163,54 @@ impl Preprocessor for TrplListing {
     }
 
     fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let mode = Mode::from_context(ctx, self.name())?;
+        let Config {
+            mode,
+            rewrite,
+            labels,
+            ..
+        } = Config::from_context(ctx, self.name())?;
+
+        let src_dir = &ctx.config.book.src;
+
+        assign_numbers(&mut book, src_dir).map_err(Diagnostics)?;
+        let index =
+            ListingIndex::from_book(&book, src_dir).map_err(Diagnostics)?;
 
+        // Assigning numbers only changes the `number` attribute, after where
+        // errors about a listing point, but resolving references moves the
+        // rest of its line, so errors after that are mapped back to the
+        // chapter's text as it was before.
         let mut errors = vec![];
         book.for_each_mut(|item| {
             if let BookItem::Chapter(ref mut chapter) = item {
-                match rewrite_listing(&chapter.content, mode) {
+                let path = chapter_path(src_dir, chapter);
+                let (resolved, map) = match resolve_references(
+                    &chapter.content,
+                    &index,
+                    chapter.path.as_deref(),
+                    mode,
+                    &labels,
+                ) {
+                    Ok(resolved) => resolved,
+                    Err(reasons) => {
+                        errors.extend(reasons.into_iter().map(|reason| {
+                            Diagnostic::new(&path, &chapter.content, reason)
+                        }));
+                        return;
+                    }
+                };
+
+                match rewrite_listing(&resolved, mode, rewrite, &labels) {
                     Ok(rewritten) => chapter.content = rewritten,
-                    Err(reason) => errors.push(anyhow!(reason)),
+                    Err(reasons) => {
+                        errors.extend(reasons.into_iter().map(|reason| {
+                            Diagnostic::new(
+                                &path,
+                                &chapter.content,
+                                map.locate(reason),
+                            )
+                        }))
+                    }
                 }
             }
         });
@@ -76,7 +218,7 @@ impl Preprocessor for TrplListing {
         if errors.is_empty() {
             Ok(book)
         } else {
-            Err(CompositeError(errors).into())
+            Err(Diagnostics(errors).into())
         }
     }
 
@@ -85,127 +227,860 @@ impl Preprocessor for TrplListing {
     }
 }
 
-fn rewrite_listing(src: &str, mode: Mode) -> Result<String, String> {
+impl TrplListing {
+    /// Check that every `<Listing>` in the book matches the file it includes.
+    ///
+    /// For a listing like this:
+    ///
+    /// ````markdown
+    /// <Listing number="2-1" file-name="src/main.rs">
+    ///
+    /// ```rust
+    /// {{#rustdoc_include ../listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs:all}}
+    /// ```
+    ///
+    /// </Listing>
+    /// ````
+    ///
+    /// …the included path must end with the `file-name`, and when the path is
+    /// in a `listing-NN-MM` directory, the `number` must be `NN-MM`. Listings
+    /// without a `file-name` or `number`, includes outside of a
+    /// `listing-NN-MM` directory, and includes of an `output.txt`, which show
+    /// the output of another listing's code, are not checked.
+    ///
+    /// A listing which intentionally shows another listing's files names that
+    /// listing's directory with `reuse`, and includes from that directory are
+    /// not checked against its `number`:
+    ///
+    /// ```markdown
+    /// <Listing number="13-21" file-name="src/lib.rs" reuse="listing-12-19">
+    /// ```
+    ///
+    /// Each mismatch is reported at the opening `<Listing>` tag, in the chapter
+    /// within `src_dir`.
+    pub fn check(&self, book: &Book, src_dir: &Path) -> Vec<Diagnostic> {
+        book.iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter),
+                _ => None,
+            })
+            .flat_map(|chapter| {
+                let path = chapter_path(src_dir, chapter);
+                check_listings(&chapter.content).into_iter().map(
+                    move |mismatch| {
+                        Diagnostic::new(&path, &chapter.content, mismatch)
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn rewrite_listing(
+    src: &str,
+    mode: Mode,
+    rewrite: Rewrite,
+    labels: &Labels,
+) -> Result<String, Vec<SourceError>> {
     match mode {
         Mode::Default => {
-            let final_state = crate::parser(src).try_fold(
-                RewriteState {
-                    current: None,
-                    events: vec![],
-                },
-                |mut state, ev| -> Result<RewriteState, String> {
-                    match ev {
-                        Event::Html(tag) => {
-                            if tag.starts_with("<Listing") {
-                                state.open_listing(tag, mode)?;
-                            } else if tag.starts_with("</Listing>") {
-                                state.close_listing(tag);
-                            } else {
-                                state.events.push(Ok(Event::Html(tag)));
-                            }
+            let events =
+                render_listings(crate::parser(src).into_offset_iter(), labels)?;
+            crate::to_markdown(src, events, rewrite)
+                .map_err(|error| vec![error])
+        }
+        Mode::Simple => {
+            simplify_listings(src, labels).map(|(simplified, _)| simplified)
+        }
+    }
+}
+
+/// Replace each `<Listing>` with its plain-text form, as in the simple mode,
+/// and map where each listing's tags went.
+pub(crate) fn simplify_listings(
+    src: &str,
+    labels: &Labels,
+) -> Result<(String, SourceMap), Vec<SourceError>> {
+    // The output text should be very slightly *shorter* than the input, so we
+    // know this is a reasonable size for the buffer.
+    let mut rewritten = String::with_capacity(src.len());
+    let mut map = SourceMap::default();
+    let mut current: Option<CurrentListing> = None;
+    let code_blocks = code_blocks(src);
+    for line in src.lines() {
+        // `line` is a slice of `src`, so this is its offset in `src`.
+        let line_start = line.as_ptr() as usize - src.as_ptr() as usize;
+        let replacement_start = rewritten.len();
+        // The tags are replaced along with the line break after them.
+        let tag_line = line_start..(line_start + line.len() + 1).min(src.len());
+
+        if is_listing_open(line) && (line.ends_with(">")) {
+            let listing = ListingBuilder::from_tag(line)
+                .map_err(|reason| vec![SourceError::new(line_start, reason)])?
+                .build(Mode::Simple);
+            rewritten.push_str(&listing.opening_text(labels));
+            map.replace(tag_line, replacement_start..rewritten.len());
+            current = Some(CurrentListing {
+                listing,
+                offset: line_start,
+                code_lines: 0,
+            });
+        } else if line == "</Listing>" {
+            let mut current = current.take().ok_or_else(|| {
+                vec![SourceError::new(line_start, ErrorKind::UnopenedListing)]
+            })?;
+            current.code_lines = code_blocks
+                .iter()
+                .filter(|(offset, _)| {
+                    (current.offset..line_start).contains(offset)
+                })
+                .map(|(_, lines)| lines)
+                .sum();
+            current.check_code_lines().map_err(|error| vec![error])?;
+            rewritten.push_str(&current.listing.closing_text("\n", labels));
+            map.replace(tag_line, replacement_start..rewritten.len());
+        } else {
+            rewritten.push_str(line);
+            rewritten.push('\n');
+        }
+    }
+
+    // Since we always push a `'\n'` onto the end of the new string and
+    // `.lines()` does not tell us whether there *was* such a character, this
+    // makes the output match the input, and thus avoids adding new newlines
+    // after conversion.
+    if !src.ends_with('\n') {
+        rewritten.pop();
+    }
+
+    Ok((rewritten, map))
+}
+
+/// Replace each `<Listing>` with a `<figure>`, as in the default mode.
+pub(crate) fn render_listings<'e>(
+    events: impl IntoIterator<Item = Spanned<'e>>,
+    labels: &Labels,
+) -> Result<Vec<Spanned<'e>>, Vec<SourceError>> {
+    let final_state = events
+        .into_iter()
+        .try_fold(
+            RewriteState {
+                current: None,
+                code: None,
+                events: vec![],
+            },
+            |mut state, (ev, range)| -> Result<RewriteState, SourceError> {
+                match ev {
+                    Event::Start(Tag::CodeBlock(_)) => {
+                        state.code = Some(String::new());
+                        state.events.push(Ok((ev, range)));
+                    }
+                    Event::End(TagEnd::CodeBlock) => {
+                        state.close_code();
+                        state.events.push(Ok((ev, range)));
+                    }
+                    Event::Text(ref text) if state.code.is_some() => {
+                        if let Some(code) = state.code.as_mut() {
+                            code.push_str(text);
                         }
-                        ev => state.events.push(Ok(ev)),
-                    };
-                    Ok(state)
-                },
-            )?;
-
-            if final_state.current.is_some() {
-                return Err("Unclosed listing".into());
+                        state.events.push(Ok((ev, range)));
+                    }
+                    Event::Html(tag) => {
+                        if is_listing_open(&tag) {
+                            state.open_listing(tag, range, labels)?;
+                        } else if tag.starts_with("</Listing>") {
+                            state.close_listing(tag, range, labels);
+                        } else {
+                            state.events.push(Ok((Event::Html(tag), range)));
+                        }
+                    }
+                    ev => state.events.push(Ok((ev, range))),
+                };
+                Ok(state)
+            },
+        )
+        .map_err(|error| vec![error])?;
+
+    if let Some(current) = final_state.current {
+        return Err(vec![SourceError::new(
+            current.offset,
+            ErrorKind::UnclosedListing,
+        )]);
+    }
+
+    let (events, errors): (Vec<_>, Vec<_>) =
+        final_state.events.into_iter().partition(|e| e.is_ok());
+
+    if !errors.is_empty() {
+        return Err(errors.into_iter().map(|e| e.unwrap_err()).collect());
+    }
+
+    Ok(events.into_iter().map(|ok| ok.unwrap()).collect())
+}
+
+/// The offset and number of lines of each code block in the text. These come
+/// from the same parse as the default mode uses, so that the simple mode counts
+/// a listing's lines the same way, whatever fences the code contains.
+fn code_blocks(src: &str) -> Vec<(usize, usize)> {
+    let mut blocks = vec![];
+    let mut current: Option<(usize, String)> = None;
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                current = Some((range.start, String::new()))
+            }
+            Event::Text(text) => {
+                if let Some((_, code)) = current.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((offset, code)) = current.take() {
+                    blocks.push((offset, code.split_inclusive('\n').count()));
+                }
             }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Is this the opening tag of a `<Listing>`, as opposed to e.g. a
+/// `<ListingRef>`?
+fn is_listing_open(tag: &str) -> bool {
+    tag.strip_prefix("<Listing").is_some_and(|rest| {
+        rest.starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+    })
+}
 
-            let (events, errors): (Vec<_>, Vec<_>) =
-                final_state.events.into_iter().partition(|e| e.is_ok());
+const AUTO_NUMBER: &str = "auto";
 
-            if !errors.is_empty() {
-                return Err(errors
-                    .into_iter()
-                    .map(|e| e.unwrap_err())
-                    .collect::<Vec<String>>()
-                    .join("\n"));
+/// Replace `number="auto"` on every `<Listing>` in the book with its assigned
+/// number, so that everything after this sees only concrete numbers.
+///
+/// This has to walk the book in document order to count listings, but
+/// [`Book::for_each_mut`] visits sections before their parent chapters, so the
+/// numbered source for each chapter is computed up front and then applied.
+pub(crate) fn assign_numbers(
+    book: &mut Book,
+    src_dir: &Path,
+) -> Result<(), Vec<Diagnostic>> {
+    let mut counters = HashMap::new();
+    let mut numbered = HashMap::new();
+    let mut errors = vec![];
+    for item in book.iter() {
+        if let BookItem::Chapter(chapter) = item {
+            let top_level = chapter
+                .number
+                .as_ref()
+                .and_then(|number| number.first().copied());
+            let counter = counters.entry(top_level).or_default();
+            match number_listings(&chapter.content, top_level, counter) {
+                Ok(rewritten) => {
+                    numbered.insert(chapter.path.clone(), rewritten);
+                }
+                Err(reason) => errors.push(Diagnostic::new(
+                    chapter_path(src_dir, chapter),
+                    &chapter.content,
+                    reason,
+                )),
             }
+        }
+    }
 
-            let mut buf = String::with_capacity(src.len() * 2);
-            cmark(events.into_iter().map(|ok| ok.unwrap()), &mut buf)
-                .map_err(|e| format!("{e}"))?;
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
-            Ok(buf)
+    book.for_each_mut(|item| {
+        if let BookItem::Chapter(ref mut chapter) = item {
+            if let Some(rewritten) = numbered.remove(&chapter.path) {
+                chapter.content = rewritten;
+            }
         }
-        Mode::Simple => {
-            // The output text should be very slightly *shorter* than the input,
-            // so we know this is a reasonable size for the buffer.
-            let mut rewritten = String::with_capacity(src.len());
-            let mut current_closing = None;
-            for line in src.lines() {
-                if line.starts_with("<Listing") && (line.ends_with(">")) {
-                    let listing =
-                        ListingBuilder::from_tag(&line)?.build(Mode::Simple);
-                    rewritten.push_str(&listing.opening_text());
-                    current_closing = Some(listing.closing_text("\n"));
-                } else if line == "</Listing>" {
-                    let closing =
-                        current_closing.as_ref().ok_or_else(|| {
-                            String::from(
-                                "Closing `</Listing>` without opening tag.",
-                            )
-                        })?;
-                    rewritten.push_str(closing);
-                } else {
-                    rewritten.push_str(line);
-                    rewritten.push('\n');
+    });
+
+    Ok(())
+}
+
+/// Assign a number to every `<Listing number="auto">` in a single chapter.
+///
+/// `counter` is the number of numbered listings already seen in the top-level
+/// `chapter`, and is updated for every numbered listing in this text, whether
+/// its number is assigned automatically or by hand.
+fn number_listings(
+    src: &str,
+    chapter: Option<u32>,
+    counter: &mut u32,
+) -> Result<String, SourceError> {
+    let mut rewritten = String::with_capacity(src.len());
+    let mut last_end = 0;
+
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        let Event::Html(tag) = event else {
+            continue;
+        };
+
+        if !is_listing_open(&tag) {
+            continue;
+        }
+
+        // Malformed tags are reported when rewriting the listing.
+        let Ok(ListingBuilder {
+            number: Some(number),
+            ..
+        }) = ListingBuilder::from_tag(tag.trim_end())
+        else {
+            continue;
+        };
+
+        *counter += 1;
+        if number != AUTO_NUMBER {
+            continue;
+        }
+
+        let Some(chapter) = chapter else {
+            return Err(SourceError::new(
+                range.start,
+                ErrorKind::UnnumberedChapter(String::from(tag.trim_end())),
+            ));
+        };
+
+        let attribute = ["number=\"auto\"", "number='auto'"]
+            .into_iter()
+            .find_map(|attr| {
+                src[range.clone()].find(attr).map(|offset| {
+                    range.start + offset..range.start + offset + attr.len()
+                })
+            })
+            .ok_or_else(|| {
+                SourceError::new(
+                    range.start,
+                    ErrorKind::MissingNumber(String::from(tag.trim_end())),
+                )
+            })?;
+
+        rewritten.push_str(&src[last_end..attribute.start]);
+        rewritten.push_str(&format!("number=\"{chapter}-{counter}\""));
+        last_end = attribute.end;
+    }
+
+    rewritten.push_str(&src[last_end..]);
+    Ok(rewritten)
+}
+
+/// All the listing numbers defined anywhere in the book, along with the path to
+/// the chapter which defines each of them, so that `<ListingRef>`s can link to
+/// them.
+#[derive(Debug, Default)]
+pub(crate) struct ListingIndex {
+    chapters: HashMap<String, Option<PathBuf>>,
+    names: HashMap<String, String>,
+}
+
+impl ListingIndex {
+    pub(crate) fn from_book(
+        book: &Book,
+        src_dir: &Path,
+    ) -> Result<ListingIndex, Vec<Diagnostic>> {
+        let mut index = ListingIndex::default();
+        let mut errors = vec![];
+        for item in book.iter() {
+            if let BookItem::Chapter(chapter) = item {
+                let mut chapter_errors = vec![];
+                index.add_chapter(
+                    &chapter.content,
+                    chapter.path.as_deref(),
+                    &mut chapter_errors,
+                );
+                errors.extend(chapter_errors.into_iter().map(|error| {
+                    Diagnostic::new(
+                        chapter_path(src_dir, chapter),
+                        &chapter.content,
+                        error,
+                    )
+                }));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(index)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Record every numbered listing in the chapter. Malformed `<Listing>`
+    /// tags are ignored here; they are reported when rewriting the chapter.
+    fn add_chapter(
+        &mut self,
+        src: &str,
+        path: Option<&Path>,
+        errors: &mut Vec<SourceError>,
+    ) {
+        for (event, range) in crate::parser(src).into_offset_iter() {
+            let Event::Html(tag) = event else {
+                continue;
+            };
+
+            if !is_listing_open(&tag) {
+                continue;
+            }
+
+            let Ok(ListingBuilder {
+                number: Some(number),
+                name,
+                ..
+            }) = ListingBuilder::from_tag(tag.trim_end())
+            else {
+                continue;
+            };
+
+            if let Some(name) = name {
+                match self.names.entry(name) {
+                    Entry::Occupied(entry) => errors.push(SourceError::new(
+                        range.start,
+                        ErrorKind::DuplicateListingName(entry.key().clone()),
+                    )),
+                    Entry::Vacant(entry) => {
+                        entry.insert(number.clone());
+                    }
                 }
             }
 
-            // Since we always push a `'\n'` onto the end of the new string and
-            // `.lines()` does not tell us whether there *was* such a character,
-            // this makes the output match the input, and thus avoids adding new
-            // newlines after conversion.
-            if !src.ends_with('\n') {
-                rewritten.pop();
+            match self.chapters.entry(number) {
+                Entry::Occupied(entry) => errors.push(SourceError::new(
+                    range.start,
+                    ErrorKind::DuplicateListingNumber(entry.key().clone()),
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert(path.map(PathBuf::from));
+                }
             }
+        }
+    }
 
-            Ok(rewritten)
+    /// Get the number of the listing a `<ListingRef>` points to.
+    fn number_for(&self, reference: &ListingRef) -> Option<&str> {
+        match reference {
+            ListingRef::Number(number) => self
+                .chapters
+                .get_key_value(number)
+                .map(|(number, _)| number.as_str()),
+            ListingRef::Name(name) => self.names.get(name).map(String::as_str),
         }
     }
+
+    /// Get the link to a listing from the chapter at `from`.
+    fn href(&self, number: &str, from: Option<&Path>) -> Option<String> {
+        let target = self.chapters.get(number)?;
+        Some(href(target.as_deref(), from, &format!("listing-{number}")))
+    }
+}
+
+/// Get the link to the element with `id` in the `target` chapter from the
+/// chapter at `from`, which is only the fragment within the same chapter.
+pub(crate) fn href(
+    target: Option<&Path>,
+    from: Option<&Path>,
+    id: &str,
+) -> String {
+    let page = match (target, from) {
+        (Some(target), Some(from)) if target != from => {
+            relative_html_path(target, from)
+        }
+        (Some(target), None) => relative_html_path(target, Path::new("")),
+        _ => String::new(),
+    };
+    format!("{page}#{id}")
+}
+
+/// Get the path to the rendered HTML for the `target` chapter relative to the
+/// `from` chapter, with both paths relative to the book's `src` directory.
+fn relative_html_path(target: &Path, from: &Path) -> String {
+    let depth = from
+        .parent()
+        .map(|dir| {
+            dir.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or_default();
+
+    let target = target
+        .with_extension("html")
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    format!("{}{target}", "../".repeat(depth))
+}
+
+/// Replace every `<ListingRef number="..."/>` in the text with a reference to
+/// the corresponding listing: a link in the default mode, and plain text in the
+/// simple mode.
+pub(crate) fn resolve_references(
+    src: &str,
+    index: &ListingIndex,
+    path: Option<&Path>,
+    mode: Mode,
+    labels: &Labels,
+) -> Result<(String, SourceMap), Vec<SourceError>> {
+    let mut rewritten = String::with_capacity(src.len());
+    let mut map = SourceMap::default();
+    let mut errors = vec![];
+    let mut last_end = 0;
+
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        let (Event::Html(_) | Event::InlineHtml(_)) = event else {
+            continue;
+        };
+
+        // An HTML block can contain any number of references.
+        let mut next = range.start;
+        while let Some(start) = src[next..range.end]
+            .find("<ListingRef")
+            .map(|offset| next + offset)
+        {
+            let Some(end) = src[start..range.end]
+                .find('>')
+                .map(|offset| start + offset + 1)
+            else {
+                errors.push(SourceError::new(
+                    start,
+                    ErrorKind::UnclosedListingRef,
+                ));
+                break;
+            };
+            next = end;
+
+            let reference = match ListingRef::from_tag(&src[start..end]) {
+                Ok(reference) => reference,
+                Err(reason) => {
+                    errors.push(SourceError::new(start, reason));
+                    continue;
+                }
+            };
+
+            let Some((number, href)) =
+                index.number_for(&reference).and_then(|number| {
+                    index.href(number, path).map(|href| (number, href))
+                })
+            else {
+                errors.push(SourceError::new(
+                    start,
+                    ErrorKind::NonexistentListing(reference.to_string()),
+                ));
+                continue;
+            };
+
+            let label = &labels.listing;
+            rewritten.push_str(&src[last_end..start]);
+            let replacement_start = rewritten.len();
+            match mode {
+                Mode::Default => rewritten.push_str(&format!(
+                    "<a href=\"{href}\">{label} {number}</a>"
+                )),
+                Mode::Simple => {
+                    rewritten.push_str(&format!("{label} {number}"))
+                }
+            }
+            map.replace(start..end, replacement_start..rewritten.len());
+            last_end = end;
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    rewritten.push_str(&src[last_end..]);
+    Ok((rewritten, map))
+}
+
+/// A reference to a listing, either by number, e.g.
+/// `<ListingRef number="1-2"/>`, or by name, e.g.
+/// `<ListingRef name="hello-world"/>`.
+#[derive(Debug, PartialEq)]
+enum ListingRef {
+    Number(String),
+    Name(String),
+}
+
+impl ListingRef {
+    fn from_tag(tag: &str) -> Result<ListingRef, ErrorKind> {
+        let mut reference = None;
+        for (key, value) in tag_attributes(tag)? {
+            let next = match (key.as_str(), value) {
+                ("number", Some(value)) => ListingRef::Number(value),
+                ("name", Some(value)) => ListingRef::Name(value),
+                (attr @ "number", None) | (attr @ "name", None) => {
+                    return Err(ErrorKind::MissingValue(String::from(attr)))
+                }
+                (attr, _) => {
+                    return Err(ErrorKind::UnsupportedAttribute(String::from(
+                        attr,
+                    )))
+                }
+            };
+
+            if reference.replace(next).is_some() {
+                return Err(ErrorKind::AmbiguousListingRef);
+            }
+        }
+
+        reference.ok_or(ErrorKind::MissingListingRefTarget)
+    }
+}
+
+impl std::fmt::Display for ListingRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListingRef::Number(number) => write!(f, "{number}"),
+            ListingRef::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Check each `<Listing>` in a chapter against the files it includes, reporting
+/// each mismatch at the opening `<Listing>` tag.
+fn check_listings(src: &str) -> Vec<SourceError> {
+    let mut mismatches = vec![];
+    let mut current: Option<(usize, ListingBuilder, Vec<String>)> = None;
+
+    for (event, range) in crate::parser(src).into_offset_iter() {
+        match event {
+            Event::Html(tag) if is_listing_open(&tag) => {
+                // Malformed tags are reported when rewriting the listing.
+                current = ListingBuilder::from_tag(tag.trim_end())
+                    .ok()
+                    .map(|builder| (range.start, builder, vec![]));
+            }
+
+            Event::Html(tag) if tag.starts_with("</Listing>") => {
+                if let Some((offset, builder, includes)) = current.take() {
+                    mismatches.extend(
+                        check_listing(&builder, &includes)
+                            .into_iter()
+                            .map(|mismatch| SourceError::new(offset, mismatch)),
+                    );
+                }
+            }
+
+            Event::Text(text) => {
+                if let Some((_, _, includes)) = current.as_mut() {
+                    includes.extend(
+                        included_paths(&text)
+                            .into_iter()
+                            .filter(|path| !is_output(path)),
+                    );
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    mismatches
+}
+
+fn check_listing(
+    listing: &ListingBuilder,
+    includes: &[String],
+) -> Vec<ErrorKind> {
+    let mut mismatches = vec![];
+
+    if let Some(file_name) = &listing.file_name {
+        let matches_file_name =
+            |include: &String| Path::new(include).ends_with(file_name);
+        if !includes.is_empty() && !includes.iter().any(matches_file_name) {
+            mismatches.push(ErrorKind::FileNameMismatch {
+                file_name: file_name.clone(),
+                included: includes.join("', '"),
+            });
+        }
+    }
+
+    if let Some(number) = listing.number.as_deref().and_then(parse_number) {
+        for include in includes {
+            let Some((dir, dir_number)) = Path::new(include)
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(part) => part.to_str(),
+                    _ => None,
+                })
+                .find_map(|part| {
+                    part.strip_prefix("listing-")
+                        .and_then(parse_number)
+                        .map(|dir_number| (part, dir_number))
+                })
+            else {
+                continue;
+            };
+
+            if dir_number != number && listing.reuse.as_deref() != Some(dir) {
+                mismatches.push(ErrorKind::NumberMismatch {
+                    number: listing.number.clone().unwrap_or_default(),
+                    directory: String::from(dir),
+                });
+            }
+        }
+    }
+
+    mismatches
+}
+
+/// Is this the path to the output of a listing's code, which other listings
+/// show as well?
+fn is_output(path: &str) -> bool {
+    Path::new(path)
+        .file_name()
+        .is_some_and(|name| name == "output.txt")
+}
+
+/// Parse a listing number like `2-1` (or `02-01`, as in a listing directory
+/// name) into its chapter and listing parts.
+fn parse_number(number: &str) -> Option<(u32, u32)> {
+    let (chapter, listing) = number.split_once('-')?;
+    Some((chapter.parse().ok()?, listing.parse().ok()?))
+}
+
+/// Get the paths from any `{{#include ...}}` or `{{#rustdoc_include ...}}`
+/// directives in the text, without any anchor or line range.
+fn included_paths(text: &str) -> Vec<String> {
+    text.split("{{#")
+        .skip(1)
+        .filter_map(|directive| {
+            let directive = directive.split("}}").next()?;
+            let path = directive
+                .strip_prefix("rustdoc_include")
+                .or_else(|| directive.strip_prefix("include"))?
+                .trim();
+            let path = path.split(':').next().unwrap_or(path);
+            Some(String::from(path))
+        })
+        .collect()
+}
+
+/// Parse the attributes from a single (opening or self-closing) tag.
+pub(crate) fn tag_attributes(
+    tag: &str,
+) -> Result<impl Iterator<Item = (String, Option<String>)>, ErrorKind> {
+    Ok(Dom::parse(tag)
+        .map_err(|e| ErrorKind::InvalidHtml(e.to_string()))?
+        .children
+        .into_iter()
+        .filter_map(|node| match node {
+            html_parser::Node::Element(element) => Some(element.attributes),
+            html_parser::Node::Text(_) | html_parser::Node::Comment(_) => None,
+        })
+        .flatten())
 }
 
 struct RewriteState<'e> {
-    current: Option<Listing>,
-    events: Vec<Result<Event<'e>, String>>,
+    current: Option<CurrentListing>,
+    /// The text of the code block currently being rewritten, if any.
+    code: Option<String>,
+    events: Vec<Result<Spanned<'e>, SourceError>>,
+}
+
+/// The listing currently being rewritten.
+struct CurrentListing {
+    listing: Listing,
+    /// The offset of the opening tag in the source.
+    offset: usize,
+    /// How many lines of code the listing has had so far.
+    code_lines: usize,
 }
 
-impl<'e> RewriteState<'e> {
+impl CurrentListing {
+    fn check_code_lines(&self) -> Result<(), SourceError> {
+        for (attr, ranges) in [
+            ("highlight", &self.listing.highlight),
+            ("dim", &self.listing.dim),
+        ] {
+            if let Some(last) = ranges.last() {
+                if last > self.code_lines {
+                    return Err(SourceError::new(
+                        self.offset,
+                        ErrorKind::LineOutOfRange {
+                            attribute: attr,
+                            line: last,
+                            lines: self.code_lines,
+                        },
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RewriteState<'_> {
+    /// Count the lines of the code block which just ended toward the current
+    /// listing's.
+    fn close_code(&mut self) {
+        let Some(code) = self.code.take() else {
+            return;
+        };
+
+        if let Some(current) = self.current.as_mut() {
+            current.code_lines += code.split_inclusive('\n').count();
+        }
+    }
+
     fn open_listing(
         &mut self,
         tag: pulldown_cmark::CowStr<'_>,
-        mode: Mode,
-    ) -> Result<(), String> {
-        let listing = ListingBuilder::from_tag(&tag)?.build(mode);
-        let opening_event = Event::Html(listing.opening_html().into());
+        range: Range<usize>,
+        labels: &Labels,
+    ) -> Result<(), SourceError> {
+        let offset = range.start;
+        let listing = ListingBuilder::from_tag(&tag)
+            .map_err(|reason| SourceError::new(offset, reason))?
+            .build(Mode::Default);
+        let opening_event = Event::Html(listing.opening_html(labels).into());
 
-        self.current = Some(listing);
-        self.events.push(Ok(opening_event));
+        self.current = Some(CurrentListing {
+            listing,
+            offset,
+            code_lines: 0,
+        });
+        self.events.push(Ok((opening_event, range)));
         Ok(())
     }
 
-    fn close_listing(&mut self, tag: pulldown_cmark::CowStr<'_>) {
+    fn close_listing(
+        &mut self,
+        tag: pulldown_cmark::CowStr<'_>,
+        range: Range<usize>,
+        labels: &Labels,
+    ) {
         let trailing = if !tag.ends_with('>') {
             tag.replace("</Listing>", "")
         } else {
             String::from("")
         };
 
-        match &self.current {
-            Some(listing) => {
-                let closing_event =
-                    Event::Html(listing.closing_html(&trailing).into());
+        match self.current.take() {
+            Some(current) => {
+                if let Err(error) = current.check_code_lines() {
+                    self.events.push(Err(error));
+                }
 
-                self.current = None;
-                self.events.push(Ok(closing_event));
+                let closing_event = Event::Html(
+                    current.listing.closing_html(&trailing, labels).into(),
+                );
+                self.events.push(Ok((closing_event, range)));
             }
             None => {
-                self.events.push(Err(String::from(
-                    "Closing `</Listing>` without opening tag.",
+                self.events.push(Err(SourceError::new(
+                    range.start,
+                    ErrorKind::UnopenedListing,
                 )));
             }
         }
@@ -217,35 +1092,53 @@ struct Listing {
     number: Option<String>,
     caption: Option<String>,
     file_name: Option<String>,
+    highlight: LineRanges,
+    dim: LineRanges,
 }
 
 impl Listing {
-    fn opening_html(&self) -> String {
+    fn opening_html(&self, labels: &Labels) -> String {
         let id_attribute = self
             .number
             .as_ref()
             .map(|number| format!(" id=\"listing-{number}\""))
             .unwrap_or_default();
 
-        let figure = format!("<figure class=\"listing\"{id_attribute}>\n");
+        let mut lines_attributes = String::new();
+        for (attr, ranges) in
+            [("highlight", &self.highlight), ("dim", &self.dim)]
+        {
+            if !ranges.is_empty() {
+                lines_attributes
+                    .push_str(&format!(" data-{attr}=\"{ranges}\""));
+            }
+        }
+
+        let figure = format!(
+            "<figure class=\"listing\"{id_attribute}{lines_attributes}>\n"
+        );
 
         match self.file_name.as_ref() {
-            Some(file_name) => format!(
-                "{figure}<span class=\"file-name\">Filename: {file_name}</span>\n",
-            ),
+            Some(file_name) => {
+                let label = labels.file_name.as_deref().unwrap_or("Filename:");
+                format!(
+                    "{figure}<span class=\"file-name\">{label} {file_name}</span>\n",
+                )
+            }
             None => figure,
         }
     }
 
-    fn closing_html(&self, trailing: &str) -> String {
+    fn closing_html(&self, trailing: &str, labels: &Labels) -> String {
         match (&self.number, &self.caption) {
             (Some(number), caption) => {
                 let caption_text = caption
                     .as_ref()
                     .map(|caption| format!(": {}", caption))
                     .unwrap_or_default();
+                let label = &labels.listing;
                 let listing_a_tag = format!(
-                    "<a href=\"#listing-{number}\">Listing {number}</a>"
+                    "<a href=\"#listing-{number}\">{label} {number}</a>"
                 );
                 format!(
                     r#"<figcaption>{listing_a_tag}{caption_text}</figcaption>
@@ -260,20 +1153,36 @@ impl Listing {
         }
     }
 
-    fn opening_text(&self) -> String {
-        self.file_name
+    fn opening_text(&self, labels: &Labels) -> String {
+        let file_name = self
+            .file_name
             .as_ref()
-            .map(|file_name| format!("{file_name}\n"))
-            .unwrap_or_default()
+            .map(|file_name| match &labels.file_name {
+                Some(label) => format!("{label} {file_name}\n"),
+                None => format!("{file_name}\n"),
+            })
+            .unwrap_or_default();
+        let highlight = if self.highlight.is_empty() {
+            String::new()
+        } else {
+            format!("{} {}\n", labels.highlighted_lines, self.highlight)
+        };
+        let dim = if self.dim.is_empty() {
+            String::new()
+        } else {
+            format!("{} {}\n", labels.dimmed_lines, self.dim)
+        };
+        format!("{file_name}{highlight}{dim}")
     }
 
-    fn closing_text(&self, trailing: &str) -> String {
+    fn closing_text(&self, trailing: &str, labels: &Labels) -> String {
+        let label = &labels.listing;
         match (&self.number, &self.caption) {
             (Some(number), Some(caption)) => {
-                format!("Listing {number}: {caption}{trailing}")
+                format!("{label} {number}: {caption}{trailing}")
             }
             (None, Some(caption)) => format!("{caption}{trailing}"),
-            (Some(number), None) => format!("Listing {number}{trailing}"),
+            (Some(number), None) => format!("{label} {number}{trailing}"),
             (None, None) => trailing.into(),
         }
     }
@@ -284,51 +1193,68 @@ impl Listing {
 /// while `Listing` has the *rendered* version.
 struct ListingBuilder {
     number: Option<String>,
+    name: Option<String>,
+    /// The directory of another listing whose files this one shows, which is
+    /// only used when checking the listing.
+    reuse: Option<String>,
     caption: Option<String>,
     file_name: Option<String>,
+    highlight: LineRanges,
+    dim: LineRanges,
 }
 
 impl ListingBuilder {
-    fn from_tag(tag: &str) -> Result<ListingBuilder, String> {
+    fn from_tag(tag: &str) -> Result<ListingBuilder, ErrorKind> {
         let to_parse = format!("{tag}</Listing>");
-        Dom::parse(&to_parse)
-            .map_err(|e| e.to_string())?
-            .children
-            .into_iter()
-            .filter_map(|node| match node {
-                html_parser::Node::Element(element) => Some(element.attributes),
-                html_parser::Node::Text(_) | html_parser::Node::Comment(_) => {
-                    None
+        let builder = tag_attributes(&to_parse)?.try_fold(
+            ListingBuilder {
+                number: None,
+                name: None,
+                reuse: None,
+                caption: None,
+                file_name: None,
+                highlight: LineRanges::default(),
+                dim: LineRanges::default(),
+            },
+            |builder, (key, maybe_value)| match (key.as_str(), maybe_value) {
+                ("number", Some(value)) => Ok(builder.with_number(value)),
+
+                ("name", Some(value)) => Ok(builder.with_name(value)),
+
+                ("reuse", Some(value)) => Ok(builder.with_reuse(value)),
+
+                ("caption", Some(value)) => Ok(builder.with_caption(value)),
+
+                ("file-name", Some(value)) => Ok(builder.with_file_name(value)),
+
+                (attr @ "highlight", Some(value)) => {
+                    Ok(builder.with_highlight(LineRanges::parse(attr, &value)?))
                 }
-            })
-            .flatten()
-            .try_fold(
-                ListingBuilder {
-                    number: None,
-                    caption: None,
-                    file_name: None,
-                },
-                |builder, (key, maybe_value)| match (key.as_str(), maybe_value)
-                {
-                    ("number", Some(value)) => Ok(builder.with_number(value)),
-
-                    ("caption", Some(value)) => Ok(builder.with_caption(value)),
-
-                    ("file-name", Some(value)) => {
-                        Ok(builder.with_file_name(value))
-                    }
 
-                    (attr @ "file-name", None)
-                    | (attr @ "caption", None)
-                    | (attr @ "number", None) => {
-                        Err(format!("Missing value for attribute: '{attr}'"))
-                    }
+                (attr @ "dim", Some(value)) => {
+                    Ok(builder.with_dim(LineRanges::parse(attr, &value)?))
+                }
 
-                    (attr, _) => {
-                        Err(format!("Unsupported attribute name: '{attr}'"))
-                    }
-                },
-            )
+                (attr @ "file-name", None)
+                | (attr @ "highlight", None)
+                | (attr @ "dim", None)
+                | (attr @ "name", None)
+                | (attr @ "reuse", None)
+                | (attr @ "caption", None)
+                | (attr @ "number", None) => {
+                    Err(ErrorKind::MissingValue(String::from(attr)))
+                }
+
+                (attr, _) => {
+                    Err(ErrorKind::UnsupportedAttribute(String::from(attr)))
+                }
+            },
+        )?;
+
+        match builder.highlight.first_shared_line(&builder.dim) {
+            Some(line) => Err(ErrorKind::HighlightedAndDimmed(line)),
+            None => Ok(builder),
+        }
     }
 
     fn with_number(mut self, value: String) -> Self {
@@ -336,6 +1262,16 @@ impl ListingBuilder {
         self
     }
 
+    fn with_name(mut self, value: String) -> Self {
+        self.name = Some(value);
+        self
+    }
+
+    fn with_reuse(mut self, value: String) -> Self {
+        self.reuse = Some(value);
+        self
+    }
+
     fn with_caption(mut self, value: String) -> Self {
         self.caption = Some(value);
         self
@@ -346,25 +1282,128 @@ impl ListingBuilder {
         self
     }
 
+    fn with_highlight(mut self, value: LineRanges) -> Self {
+        self.highlight = value;
+        self
+    }
+
+    fn with_dim(mut self, value: LineRanges) -> Self {
+        self.dim = value;
+        self
+    }
+
     fn build(self, mode: Mode) -> Listing {
         let caption = match mode {
-            Mode::Default => self.caption.map(|caption_source| {
-                let events = crate::parser(&caption_source);
-                let mut buf = String::with_capacity(caption_source.len() * 2);
-                html::push_html(&mut buf, events);
-
-                // This is not particularly principled, but since the only
-                // place it is used is here, for caption source handling, it
-                // is “fine”.
-                buf.replace("<p>", "").replace("</p>", "").replace('\n', "")
-            }),
+            Mode::Default => {
+                self.caption.map(|caption| crate::inline_html(&caption))
+            }
             Mode::Simple => self.caption,
         };
 
         Listing {
-            number: self.number.map(String::from),
+            number: self.number,
             caption,
-            file_name: self.file_name.map(String::from),
+            file_name: self.file_name,
+            highlight: self.highlight,
+            dim: self.dim,
+        }
+    }
+}
+
+/// The lines given to a `highlight` or `dim` attribute, like `3-5,9`: sorted,
+/// non-overlapping, inclusive ranges of line numbers starting from 1.
+#[derive(Debug, Default, PartialEq)]
+struct LineRanges(Vec<RangeInclusive<usize>>);
+
+impl LineRanges {
+    fn parse(attr: &str, value: &str) -> Result<LineRanges, ErrorKind> {
+        let invalid = |part: &str, reason| ErrorKind::InvalidLineRange {
+            attribute: String::from(attr),
+            range: String::from(part),
+            reason,
+        };
+
+        let mut ranges = value
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                let parse = |line: &str| {
+                    line.trim()
+                        .parse::<usize>()
+                        .map_err(|_| invalid(part, "expected a line number"))
+                };
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start == 0 {
+                    Err(invalid(part, "lines are numbered from 1"))
+                } else if start > end {
+                    Err(invalid(part, "the range ends before it starts"))
+                } else {
+                    Ok(start..=end)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ranges.sort_by_key(|range| *range.start());
+        if let Some(pair) = ranges
+            .windows(2)
+            .find(|pair| pair[1].start() <= pair[0].end())
+        {
+            return Err(ErrorKind::OverlappingLineRanges {
+                attribute: String::from(attr),
+                first: DisplayRange(&pair[0]).to_string(),
+                second: DisplayRange(&pair[1]).to_string(),
+            });
+        }
+
+        Ok(LineRanges(ranges))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The highest line number in the ranges.
+    fn last(&self) -> Option<usize> {
+        self.0.last().map(|range| *range.end())
+    }
+
+    /// The lowest line number in both sets of ranges, found by intersecting the
+    /// ranges rather than visiting every line, since they can be huge.
+    fn first_shared_line(&self, other: &LineRanges) -> Option<usize> {
+        self.0
+            .iter()
+            .flat_map(|a| {
+                other.0.iter().filter_map(move |b| {
+                    let start = *a.start().max(b.start());
+                    (start <= *a.end().min(b.end())).then_some(start)
+                })
+            })
+            .min()
+    }
+}
+
+impl fmt::Display for LineRanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, range) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", DisplayRange(range))?;
+        }
+        Ok(())
+    }
+}
+
+struct DisplayRange<'a>(&'a RangeInclusive<usize>);
+
+impl fmt::Display for DisplayRange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = (self.0.start(), self.0.end());
+        if start == end {
+            write!(f, "{start}")
+        } else {
+            write!(f, "{start}-{end}")
         }
     }
 }
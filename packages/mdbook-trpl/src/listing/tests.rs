@@ -1,4 +1,6 @@
 use super::*;
+use crate::config::Rewrite;
+use crate::diagnostic::{ErrorKind, SourceError};
 
 /// Note: This inserts an additional backtick around the re-emitted code.
 /// It is not clear *why*, but that seems to be an artifact of the rendering
@@ -14,6 +16,8 @@ fn main() {}
 
 </Listing>"#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert_eq!(
@@ -45,6 +49,8 @@ fn main() {}
 
 Trailing text."#,
         Mode::Simple,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert_eq!(
@@ -63,6 +69,25 @@ Trailing text."#
     );
 }
 
+#[test]
+fn reuse_is_not_rendered() {
+    for mode in [Mode::Default, Mode::Simple] {
+        let render = |tag: &str| {
+            rewrite_listing(
+                &format!("{tag}\n\n```rust\nfn main() {{}}\n```\n\n</Listing>"),
+                mode,
+                Rewrite::Reserialize,
+                &Labels::default(),
+            )
+        };
+
+        assert_eq!(
+            render(r#"<Listing number="13-21" reuse="listing-12-19">"#),
+            render(r#"<Listing number="13-21">"#),
+        );
+    }
+}
+
 #[test]
 fn listing_with_embedded_angle_brackets() {
     let result = rewrite_listing(
@@ -76,6 +101,8 @@ fn get_a_box_of<T>(t: T) -> Box<T> {
 
 </Listing>"#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert_eq!(
@@ -110,6 +137,8 @@ fn main() {
 
 Save the file and go back to your terminal window"#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert!(result.is_ok());
@@ -148,6 +177,8 @@ fn main() {}
 
 This is the closing."#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert!(result.is_ok());
@@ -179,6 +210,8 @@ fn main() {}
 
 </Listing>"#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert!(result.is_ok());
@@ -206,11 +239,16 @@ fn main() {}
 
 </Listing>",
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert_eq!(
         result,
-        Err(String::from("Unsupported attribute name: 'invalid-attr'"))
+        Err(vec![SourceError::new(
+            0,
+            ErrorKind::UnsupportedAttribute(String::from("invalid-attr"))
+        )])
     )
 }
 
@@ -225,11 +263,16 @@ fn main() {}
 
 </Listing>"#,
         Mode::Default,
+        Rewrite::Reserialize,
+        &Labels::default(),
     );
 
     assert_eq!(
         result,
-        Err(String::from("Unsupported attribute name: 'invalid-attr'"))
+        Err(vec![SourceError::new(
+            0,
+            ErrorKind::UnsupportedAttribute(String::from("invalid-attr"))
+        )])
     )
 }
 
@@ -248,11 +291,16 @@ fn main() {}
 
 </Listing>"#,
             Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
         );
 
         assert_eq!(
             result,
-            Err(String::from("Missing value for attribute: 'number'"))
+            Err(vec![SourceError::new(
+                0,
+                ErrorKind::MissingValue(String::from("number"))
+            )])
         )
     }
 
@@ -267,11 +315,16 @@ fn main() {}
 
 </Listing>"#,
             Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
         );
 
         assert_eq!(
             result,
-            Err(String::from("Missing value for attribute: 'caption'"))
+            Err(vec![SourceError::new(
+                0,
+                ErrorKind::MissingValue(String::from("caption"))
+            )])
         )
     }
 
@@ -286,11 +339,1052 @@ fn main() {}
 
 </Listing>"#,
             Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            result,
+            Err(vec![SourceError::new(
+                0,
+                ErrorKind::MissingValue(String::from("file-name"))
+            )])
+        )
+    }
+}
+
+mod labels {
+    use super::*;
+
+    fn italian() -> Labels {
+        Labels {
+            listing: String::from("Codice"),
+            file_name: Some(String::from("File:")),
+            ..Labels::default()
+        }
+    }
+
+    #[test]
+    fn default_mode() {
+        let result = rewrite_listing(
+            r#"<Listing number="4-1" file-name="src/main.rs" caption="Un esempio">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &italian(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r##"<figure class="listing" id="listing-4-1">
+<span class="file-name">File: src/main.rs</span>
+
+````rust
+fn main() {}
+````
+
+<figcaption><a href="#listing-4-1">Codice 4-1</a>: Un esempio</figcaption>
+</figure>"##
+        );
+    }
+
+    #[test]
+    fn simple_mode() {
+        let result = rewrite_listing(
+            r#"<Listing number="4-1" file-name="src/main.rs" caption="Un esempio">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &italian(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r#"File: src/main.rs
+
+```rust
+fn main() {}
+```
+
+Codice 4-1: Un esempio"#
+        );
+    }
+}
+
+mod references {
+    use mdbook::book::Chapter;
+
+    use super::*;
+
+    fn index() -> ListingIndex {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Chapter 1",
+            String::from(
+                r#"<Listing number="1-1" caption="Hello">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            ),
+            "ch01-01-hello.md",
+            vec![],
+        ));
+        book.push_item(Chapter::new(
+            "Chapter 4",
+            String::from(
+                r#"<Listing number="4-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            ),
+            "nested/ch04-01-ownership.md",
+            vec![],
+        ));
+        ListingIndex::from_book(&book, Path::new("src")).unwrap()
+    }
+
+    #[test]
+    fn same_chapter() {
+        let result = resolve_references(
+            r#"See <ListingRef number="1-1"/> for details."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result.unwrap(),
+            r##"See <a href="#listing-1-1">Listing 1-1</a> for details."##
+        );
+    }
+
+    #[test]
+    fn other_chapter() {
+        let result = resolve_references(
+            r#"See <ListingRef number="4-1"/> for details."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result.unwrap(),
+            r##"See <a href="nested/ch04-01-ownership.html#listing-4-1">Listing 4-1</a> for details."##
+        );
+    }
+
+    #[test]
+    fn from_nested_chapter() {
+        let result = resolve_references(
+            r#"See <ListingRef number="1-1"/> for details."#,
+            &index(),
+            Some(Path::new("nested/ch04-01-ownership.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result.unwrap(),
+            r##"See <a href="../ch01-01-hello.html#listing-1-1">Listing 1-1</a> for details."##
+        );
+    }
+
+    #[test]
+    fn simple_mode() {
+        let result = resolve_references(
+            r#"<ListingRef number="1-1"/> and <ListingRef number="4-1"/> differ."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Simple,
+            &Labels::default(),
+        )
+.map(|(resolved, _)| resolved);
+
+        assert_eq!(result.unwrap(), "Listing 1-1 and Listing 4-1 differ.");
+    }
+
+    #[test]
+    fn localized() {
+        let result = resolve_references(
+            r#"Vedi <ListingRef number="1-1"/>."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels {
+                listing: String::from("Codice"),
+                ..Labels::default()
+            },
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result.unwrap(),
+            r##"Vedi <a href="#listing-1-1">Codice 1-1</a>."##
+        );
+    }
+
+    #[test]
+    fn on_its_own_line() {
+        let result = resolve_references(
+            "Some text.\n\n<ListingRef number=\"4-1\"/>\n\nMore text.",
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Simple,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(result.unwrap(), "Some text.\n\nListing 4-1\n\nMore text.");
+    }
+
+    #[test]
+    fn several_in_one_html_block() {
+        let result = resolve_references(
+            "<ListingRef number=\"1-1\"/>\nand <ListingRef number=\"4-1\"/>\n",
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Simple,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(result.unwrap(), "Listing 1-1\nand Listing 4-1\n");
+    }
+
+    #[test]
+    fn nonexistent_listing_in_html_block() {
+        let result = resolve_references(
+            "<ListingRef number=\"1-1\"/>\nand <ListingRef number=\"1-2\"/>\n",
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result,
+            Err(vec![SourceError::new(
+                31,
+                ErrorKind::NonexistentListing(String::from("1-2"))
+            )])
+        );
+    }
+
+    #[test]
+    fn in_code_is_untouched() {
+        let text = "Use `<ListingRef number=\"9-9\"/>` to refer to a listing.";
+        let result = resolve_references(
+            text,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(result.unwrap(), text);
+    }
+
+    #[test]
+    fn nonexistent_listing() {
+        let result = resolve_references(
+            r#"See <ListingRef number="1-1"/> and <ListingRef number="9-9"/>."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result,
+            Err(vec![SourceError::new(
+                35,
+                ErrorKind::NonexistentListing(String::from("9-9"))
+            )])
         );
+    }
+
+    #[test]
+    fn missing_number() {
+        let result = resolve_references(
+            r#"See <ListingRef/>."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+        .map(|(resolved, _)| resolved);
 
         assert_eq!(
             result,
-            Err(String::from("Missing value for attribute: 'file-name'"))
+            Err(vec![SourceError::new(
+                4,
+                ErrorKind::MissingListingRefTarget
+            )])
+        );
+    }
+
+    #[test]
+    fn unsupported_attribute() {
+        let result = resolve_references(
+            r#"See <ListingRef number="1-1" chapter="1"/>."#,
+            &index(),
+            Some(Path::new("ch01-01-hello.md")),
+            Mode::Default,
+            &Labels::default(),
         )
+        .map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result,
+            Err(vec![SourceError::new(
+                4,
+                ErrorKind::UnsupportedAttribute(String::from("chapter"))
+            )])
+        );
+    }
+}
+
+mod auto_number {
+    use mdbook::book::{Chapter, SectionNumber};
+
+    use super::*;
+
+    fn chapter(number: Vec<u32>, path: &str, content: &str) -> Chapter {
+        let mut chapter =
+            Chapter::new(path, String::from(content), path, vec![]);
+        chapter.number = Some(SectionNumber(number));
+        chapter
+    }
+
+    fn content_of(book: &Book, path: &str) -> String {
+        book.iter()
+            .find_map(|item| match item {
+                BookItem::Chapter(chapter)
+                    if chapter.path.as_deref() == Some(Path::new(path)) =>
+                {
+                    Some(chapter.content.clone())
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn numbers_in_document_order() {
+        let mut counter = 0;
+        let result = number_listings(
+            r#"<Listing number="auto" caption="First">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number="auto" caption="Second">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            Some(4),
+            &mut counter,
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            r#"<Listing number="4-1" caption="First">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number="4-2" caption="Second">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#
+        );
+        assert_eq!(counter, 2);
+    }
+
+    #[test]
+    fn counts_explicit_numbers_and_skips_unnumbered() {
+        let mut counter = 0;
+        let result = number_listings(
+            r#"<Listing number="4-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number='auto'>
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            Some(4),
+            &mut counter,
+        );
+
+        assert!(result.unwrap().contains(r#"<Listing number="4-2">"#));
+        assert_eq!(counter, 2);
+    }
+
+    #[test]
+    fn unnumbered_chapter() {
+        let mut counter = 0;
+        let result = number_listings(
+            r#"<Listing number="auto">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            None,
+            &mut counter,
+        );
+
+        assert_eq!(
+            result,
+            Err(SourceError::new(
+                0,
+                ErrorKind::UnnumberedChapter(String::from(
+                    r#"<Listing number="auto">"#
+                ))
+            ))
+        );
+    }
+
+    #[test]
+    fn across_sections() {
+        let listing = r#"<Listing number="auto">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#;
+
+        let mut parent = chapter(vec![4], "ch04-00.md", listing);
+        parent.sub_items.push(BookItem::Chapter(chapter(
+            vec![4, 1],
+            "ch04-01.md",
+            listing,
+        )));
+        parent.sub_items.push(BookItem::Chapter(chapter(
+            vec![4, 2],
+            "ch04-02.md",
+            listing,
+        )));
+
+        let mut book = Book::new();
+        book.push_item(chapter(vec![3], "ch03-00.md", listing));
+        book.push_item(parent);
+
+        assign_numbers(&mut book, Path::new("src")).unwrap();
+
+        assert!(content_of(&book, "ch03-00.md").contains(r#"number="3-1""#));
+        assert!(content_of(&book, "ch04-00.md").contains(r#"number="4-1""#));
+        assert!(content_of(&book, "ch04-01.md").contains(r#"number="4-2""#));
+        assert!(content_of(&book, "ch04-02.md").contains(r#"number="4-3""#));
+    }
+
+    #[test]
+    fn reference_by_name() {
+        let mut book = Book::new();
+        book.push_item(chapter(
+            vec![1],
+            "ch01-00.md",
+            r#"<Listing number="auto" name="first">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number="auto" name="second" caption="The second one">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        ));
+
+        assign_numbers(&mut book, Path::new("src")).unwrap();
+        let index = ListingIndex::from_book(&book, Path::new("src")).unwrap();
+
+        let result = resolve_references(
+            r#"Compare <ListingRef name="second"/> to <ListingRef number="1-1"/>."#,
+            &index,
+            Some(Path::new("ch01-00.md")),
+            Mode::Default,
+            &Labels::default(),
+        )
+.map(|(resolved, _)| resolved);
+
+        assert_eq!(
+            result.unwrap(),
+            r##"Compare <a href="#listing-1-2">Listing 1-2</a> to <a href="#listing-1-1">Listing 1-1</a>."##
+        );
+
+        let listing = rewrite_listing(
+            &content_of(&book, "ch01-00.md"),
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        )
+        .unwrap();
+        assert!(listing.contains(
+            r##"<figcaption><a href="#listing-1-2">Listing 1-2</a>: The second one</figcaption>"##
+        ));
+    }
+
+    #[test]
+    fn duplicate_numbers() {
+        let listing = r#"<Listing number="4-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#;
+
+        let mut book = Book::new();
+        book.push_item(chapter(vec![4], "ch04-00.md", listing));
+        book.push_item(chapter(vec![4, 1], "ch04-01.md", listing));
+
+        let result = ListingIndex::from_book(&book, Path::new("src"));
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, Path::new("src/ch04-01.md"));
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::DuplicateListingNumber(String::from("4-1"))
+        );
+    }
+
+    #[test]
+    fn both_number_and_name() {
+        let result =
+            ListingRef::from_tag(r#"<ListingRef number="1-1" name="first"/>"#);
+        assert_eq!(result, Err(ErrorKind::AmbiguousListingRef));
+    }
+}
+
+mod check {
+    use super::*;
+
+    #[test]
+    fn matching_listing() {
+        let result = check_listings(
+            r#"<Listing number="2-1" file-name="src/main.rs">
+
+```rust
+{{#rustdoc_include ../listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs:all}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(result, vec![]);
+    }
+
+    #[test]
+    fn mismatched_file_name() {
+        let result = check_listings(
+            r#"Some text.
+
+<Listing number="10-25" file-name="src/lib.rs">
+
+```rust
+{{#rustdoc_include ../listings/ch10-generic-types-traits-and-lifetimes/listing-10-25/src/main.rs}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(
+            result,
+            vec![SourceError::new(
+                12,
+                ErrorKind::FileNameMismatch {
+                    file_name: String::from("src/lib.rs"),
+                    included: String::from("../listings/ch10-generic-types-traits-and-lifetimes/listing-10-25/src/main.rs"),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn mismatched_number() {
+        let result = check_listings(
+            r#"<Listing number="7-4" file-name="src/lib.rs">
+
+```rust
+{{#include ../listings/ch07-managing-growing-projects/listing-07-03/src/lib.rs:here}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(
+            result,
+            vec![SourceError::new(
+                0,
+                ErrorKind::NumberMismatch {
+                    number: String::from("7-4"),
+                    directory: String::from("listing-07-03"),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn output_of_another_listing() {
+        let result = check_listings(
+            r#"<Listing number="7-4" caption="Compiler errors from building the code in Listing 7-3">
+
+```text
+{{#include ../listings/ch07-managing-growing-projects/listing-07-03/output.txt}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(result, vec![]);
+    }
+
+    #[test]
+    fn reused_listing() {
+        let result = check_listings(
+            r#"<Listing number="13-21" file-name="src/lib.rs" reuse="listing-12-19">
+
+```rust,ignore
+{{#rustdoc_include ../listings/ch12-an-io-project/listing-12-19/src/lib.rs:ch13}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(result, vec![]);
+    }
+
+    #[test]
+    fn reuse_only_covers_its_directory() {
+        let result = check_listings(
+            r#"<Listing number="13-21" file-name="src/lib.rs" reuse="listing-12-19">
+
+```rust,ignore
+{{#rustdoc_include ../listings/ch12-an-io-project/listing-12-20/src/lib.rs:ch13}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(
+            result,
+            vec![SourceError::new(
+                0,
+                ErrorKind::NumberMismatch {
+                    number: String::from("13-21"),
+                    directory: String::from("listing-12-20"),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn not_in_a_listing_directory() {
+        let result = check_listings(
+            r#"<Listing number="2-2" file-name="src/main.rs">
+
+```rust
+{{#include ../listings/ch02-guessing-game-tutorial/no-listing-01-cargo-new/src/main.rs}}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(result, vec![]);
+    }
+
+    #[test]
+    fn without_include() {
+        let result = check_listings(
+            r#"<Listing number="2-2" file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        );
+
+        assert_eq!(result, vec![]);
+    }
+}
+
+mod line_ranges {
+    use super::*;
+
+    const SOURCE: &str = r#"<Listing number="1-3" highlight="3-4,6" dim="1">
+
+```rust
+use std::io;
+
+fn main() {
+    let x = 1 < 2;
+    println!("{x}");
+}
+```
+
+</Listing>"#;
+
+    #[test]
+    fn default_mode() {
+        let result = rewrite_listing(
+            SOURCE,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r##"<figure class="listing" id="listing-1-3" data-highlight="3-4, 6" data-dim="1">
+
+````rust
+use std::io;
+
+fn main() {
+    let x = 1 < 2;
+    println!("{x}");
+}
+````
+
+<figcaption><a href="#listing-1-3">Listing 1-3</a></figcaption>
+</figure>"##
+        );
+    }
+
+    #[test]
+    fn simple_mode() {
+        let result = rewrite_listing(
+            SOURCE,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r#"Highlighted lines: 3-4, 6
+Dimmed lines: 1
+
+```rust
+use std::io;
+
+fn main() {
+    let x = 1 < 2;
+    println!("{x}");
+}
+```
+
+Listing 1-3"#
+        );
+    }
+
+    #[test]
+    fn simple_mode_localized() {
+        let result = rewrite_listing(
+            SOURCE,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &Labels {
+                highlighted_lines: String::from("Righe evidenziate:"),
+                dimmed_lines: String::from("Righe attenuate:"),
+                ..Labels::default()
+            },
+        );
+
+        assert!(result
+            .unwrap()
+            .starts_with("Righe evidenziate: 3-4, 6\nRighe attenuate: 1\n"));
+    }
+
+    #[test]
+    fn counts_lines_across_code_blocks() {
+        let result = rewrite_listing(
+            r#"<Listing highlight="2">
+
+```rust
+fn main() {}
+```
+
+```text
+$ cargo run
+```
+
+</Listing>"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r##"<figure class="listing" data-highlight="2">
+
+````rust
+fn main() {}
+````
+
+````text
+$ cargo run
+````
+
+</figure>"##
+        );
+    }
+
+    #[test]
+    fn keeps_code_blocks() {
+        let src = r#"<Listing number="1-4" highlight="2-3">
+
+```rust
+# use std::io;
+fn main() {
+    # let hidden = true;
+}
+```
+
+</Listing>"#;
+        let result = rewrite_listing(
+            src,
+            Mode::Default,
+            Rewrite::Splice,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            &result.unwrap(),
+            r##"<figure class="listing" id="listing-1-4" data-highlight="2-3">
+
+```rust
+# use std::io;
+fn main() {
+    # let hidden = true;
+}
+```
+
+<figcaption><a href="#listing-1-4">Listing 1-4</a></figcaption>
+</figure>"##
+        );
+    }
+
+    #[test]
+    fn parses_lines_and_ranges() {
+        assert_eq!(
+            LineRanges::parse("highlight", "9, 3-5,1"),
+            Ok(LineRanges(vec![1..=1, 3..=5, 9..=9]))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        for (value, message) in [
+            ("", "Invalid line range '' for 'dim': expected a line number"),
+            ("1-", "Invalid line range '1-' for 'dim': expected a line number"),
+            ("a", "Invalid line range 'a' for 'dim': expected a line number"),
+            ("0-2", "Invalid line range '0-2' for 'dim': lines are numbered from 1"),
+            (
+                "5-3",
+                "Invalid line range '5-3' for 'dim': the range ends before it starts",
+            ),
+            ("1-3,2", "Overlapping line ranges for 'dim': '1-3' and '2'"),
+        ] {
+            assert_eq!(
+                LineRanges::parse("dim", value).unwrap_err().to_string(),
+                message,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn highlighted_and_dimmed() {
+        let result = rewrite_listing(
+            r#"<Listing highlight="1-3" dim="3">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &Labels::default(),
+        );
+
+        assert_eq!(
+            result,
+            Err(vec![SourceError::new(
+                0,
+                ErrorKind::HighlightedAndDimmed(3)
+            )])
+        );
+    }
+
+    #[test]
+    fn fences_in_code() {
+        let src = r#"<Listing highlight="2-3">
+
+````markdown
+Some code:
+```rust
+fn main() {}
+```
+````
+
+</Listing>"#;
+        for mode in [Mode::Default, Mode::Simple] {
+            assert!(
+                rewrite_listing(
+                    src,
+                    mode,
+                    Rewrite::Reserialize,
+                    &Labels::default()
+                )
+                .is_ok(),
+                "{mode:?}"
+            );
+        }
+
+        let out_of_range = src.replace("2-3", "5");
+        for mode in [Mode::Default, Mode::Simple] {
+            assert_eq!(
+                rewrite_listing(
+                    &out_of_range,
+                    mode,
+                    Rewrite::Reserialize,
+                    &Labels::default()
+                ),
+                Err(vec![SourceError::new(
+                    0,
+                    ErrorKind::LineOutOfRange {
+                        attribute: "highlight",
+                        line: 5,
+                        lines: 4,
+                    }
+                )]),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn first_shared_line() {
+        let ranges = |value| LineRanges::parse("highlight", value).unwrap();
+
+        assert_eq!(ranges("1-3,8-10").first_shared_line(&ranges("4-7")), None);
+        assert_eq!(
+            ranges("1-3,8-10").first_shared_line(&ranges("5,9-20")),
+            Some(9)
+        );
+        assert_eq!(
+            ranges("6-10").first_shared_line(&ranges("1-2,4-7")),
+            Some(6)
+        );
+    }
+
+    #[test]
+    fn huge_ranges() {
+        let result = ListingBuilder::from_tag(
+            r#"<Listing highlight="1-3000000000" dim="2999999999-4000000000">"#,
+        );
+
+        assert_eq!(
+            result.err(),
+            Some(ErrorKind::HighlightedAndDimmed(2999999999))
+        );
+    }
+
+    #[test]
+    fn past_the_end() {
+        let src = r#"Some text.
+
+<Listing dim="2-3">
+
+```rust
+fn main() {
+}
+```
+
+</Listing>"#;
+        for mode in [Mode::Default, Mode::Simple] {
+            assert_eq!(
+                rewrite_listing(
+                    src,
+                    mode,
+                    Rewrite::Reserialize,
+                    &Labels::default()
+                ),
+                Err(vec![SourceError::new(
+                    12,
+                    ErrorKind::LineOutOfRange {
+                        attribute: "dim",
+                        line: 3,
+                        lines: 2,
+                    }
+                )]),
+                "{mode:?}"
+            );
+        }
     }
 }
@@ -4,11 +4,13 @@ use mdbook::{
     preprocess::{Preprocessor, PreprocessorContext},
     BookItem,
 };
-use pulldown_cmark::{
-    Event::{self, *},
-    Tag, TagEnd,
+use pulldown_cmark::{Event::*, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+
+use crate::{
+    config::{Config, Mode, NoteConfig, Rewrite},
+    Spanned,
 };
-use pulldown_cmark_to_cmark::cmark;
 
 /// A simple preprocessor for semantic notes in _The Rust Programming Language_.
 ///
@@ -27,17 +29,70 @@ use pulldown_cmark_to_cmark::cmark;
 ///
 /// </section>
 /// ```
+///
+/// By default, only blockquotes starting with "Note: " are notes. Translations
+/// can configure their own prefixes, and a label to show in place of them:
+///
+/// ```toml
+/// [preprocessor.trpl-note]
+/// prefixes = ["Nota: ", "Note: "]
+/// label = "Nota:"
+/// ```
+///
+/// With that configuration, both `> Nota: ...` and `> Note: ...` become notes
+/// which start with "Nota: ...".
+///
+/// Blockquotes starting with "Warning: ", "Tip: ", or "Unsafe: " become callouts
+/// of those kinds, each with its own class and ARIA role:
+///
+/// ```markdown
+/// <section class="warning" role="doc-notice" aria-label="Warning">
+///
+/// Warning: This is a warning.
+///
+/// </section>
+/// ```
+///
+/// Their prefixes and labels are configured like the note's, in a `warning`,
+/// `tip`, or `unsafe` table, and the label (or else the prefix) is also the
+/// callout's `aria-label`:
+///
+/// ```toml
+/// [preprocessor.trpl-note.warning]
+/// prefixes = ["Attenzione: ", "Warning: "]
+/// label = "Attenzione:"
+/// ```
+///
+/// When `output-mode = "simple"` in the configuration, notes and callouts stay
+/// blockquotes, like `> Note: This is a note.`, for the print version. The only
+/// change to a note in that mode is using the configured `label`, if any. Since
+/// other preprocessors may have round-tripped the Markdown, the simple mode also
+/// removes the indentation and empty leading lines that leaves on blockquotes,
+/// so it should run after them:
+///
+/// ```toml
+/// [preprocessor.trpl-note]
+/// output-mode = "simple"
+/// after = ["trpl-listing", "trpl-heading", "trpl-figure"]
+/// ```
 pub struct TrplNote;
 
 impl Preprocessor for TrplNote {
     fn name(&self) -> &str {
-        "simple-note-preprocessor"
+        "trpl-note"
     }
 
-    fn run(&self, _ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let Config {
+            mode,
+            rewrite: strategy,
+            note: config,
+            ..
+        } = Config::from_context(ctx, self.name())?;
         book.for_each_mut(|item| {
             if let BookItem::Chapter(ref mut chapter) = item {
-                chapter.content = rewrite(&chapter.content);
+                chapter.content =
+                    rewrite(&chapter.content, mode, strategy, &config);
             }
         });
         Ok(book)
@@ -48,20 +103,48 @@ impl Preprocessor for TrplNote {
     }
 }
 
-pub fn rewrite(text: &str) -> String {
-    let parser = crate::parser(text);
+pub fn rewrite(
+    text: &str,
+    mode: Mode,
+    rewrite: Rewrite,
+    config: &NoteConfig,
+) -> String {
+    match mode {
+        Mode::Default => rewrite_html(text, rewrite, config),
+        Mode::Simple => rewrite_text(text, config),
+    }
+}
 
+fn rewrite_html(text: &str, rewrite: Rewrite, config: &NoteConfig) -> String {
+    let events = render_notes(crate::parser(text).into_offset_iter(), config);
+    crate::to_markdown(text, events, rewrite).unwrap()
+}
+
+/// Replace each note or callout with a `<section>`, as in the default mode.
+pub(crate) fn render_notes<'e>(
+    input: impl IntoIterator<Item = Spanned<'e>>,
+    config: &NoteConfig,
+) -> Vec<Spanned<'e>> {
     let mut events = Vec::new();
     let mut state = Default;
 
-    for event in parser {
+    for (event, range) in input {
+        let spanned = |event| (event, range.clone());
         match (&mut state, event) {
             (Default, Start(Tag::BlockQuote(_))) => {
-                state = StartingBlockquote(vec![Start(Tag::BlockQuote(None))]);
+                state = StartingBlockquote(vec![spanned(Start(
+                    Tag::BlockQuote(None),
+                ))]);
             }
 
             (StartingBlockquote(blockquote_events), Text(content)) => {
-                if content.starts_with("Note: ") {
+                if let Some(kind) = Kind::of(&content, config) {
+                    let opening_html = kind.opening_html(&content, config);
+                    let content = match kind.relabel(&content, config) {
+                        Some(relabeled) => relabeled.into(),
+                        None => content,
+                    };
+
                     // This needs the "extra" `SoftBreak`s so that when the final rendering pass
                     // happens, it does not end up treating the internal content as inline *or*
                     // treating the HTML tags as inline tags:
@@ -72,21 +155,22 @@ pub fn rewrite(text: &str) -> String {
                     // - Along the same lines, an HTML tag that happens to be directly adjacent to
                     //   the end of a previous Markdown block will end up being rendered as part of
                     //   that block.
-                    events.extend([
-                        SoftBreak,
-                        SoftBreak,
-                        Html(
-                            r#"<section class="note" aria-role="note">"#.into(),
-                        ),
-                        SoftBreak,
-                        SoftBreak,
-                        Start(Tag::Paragraph),
-                        Text(content),
-                    ]);
+                    events.extend(
+                        [
+                            SoftBreak,
+                            SoftBreak,
+                            Html(opening_html.into()),
+                            SoftBreak,
+                            SoftBreak,
+                            Start(Tag::Paragraph),
+                            Text(content),
+                        ]
+                        .map(spanned),
+                    );
                     state = InNote;
                 } else {
                     events.append(blockquote_events);
-                    events.push(Text(content));
+                    events.push(spanned(Text(content)));
                     state = Default;
                 }
             }
@@ -95,49 +179,219 @@ pub fn rewrite(text: &str) -> String {
                 StartingBlockquote(_blockquote_events),
                 heading @ Start(Tag::Heading { .. }),
             ) => {
-                events.extend([
-                    SoftBreak,
-                    SoftBreak,
-                    Html(r#"<section class="note" aria-role="note">"#.into()),
-                    SoftBreak,
-                    SoftBreak,
-                    heading,
-                ]);
+                events.extend(
+                    [
+                        SoftBreak,
+                        SoftBreak,
+                        Html(Kind::Note.opening_html("", config).into()),
+                        SoftBreak,
+                        SoftBreak,
+                        heading,
+                    ]
+                    .map(spanned),
+                );
                 state = InNote;
             }
 
             (StartingBlockquote(ref mut events), Start(tag)) => {
-                events.push(Start(tag));
+                events.push(spanned(Start(tag)));
             }
 
             (InNote, End(TagEnd::BlockQuote(_))) => {
                 // As with the start of the block HTML, the closing HTML must be
                 // separated from the Markdown text by two newlines.
-                events.extend([
-                    SoftBreak,
-                    SoftBreak,
-                    Html("</section>".into()),
-                ]);
+                events.extend(
+                    [SoftBreak, SoftBreak, Html("</section>".into())]
+                        .map(spanned),
+                );
                 state = Default;
             }
 
             (_, event) => {
-                events.push(event);
+                events.push(spanned(event));
+            }
+        }
+    }
+
+    events
+}
+
+/// Rewrite the top-level blockquotes in `text` into their plain-text form,
+/// leaving everything else exactly as it was.
+fn rewrite_text(text: &str, config: &NoteConfig) -> String {
+    let mut rewritten = String::with_capacity(text.len());
+    let mut copied_to = 0;
+    let mut blockquote_depth = 0;
+    let mut item_depth = 0;
+
+    for (event, range) in crate::parser(text).into_offset_iter() {
+        match event {
+            Start(Tag::Item) => item_depth += 1,
+            End(TagEnd::Item) => item_depth -= 1,
+            Start(Tag::BlockQuote(_)) => {
+                blockquote_depth += 1;
+
+                // Blockquotes in list items are indented to match the item, so
+                // there is nothing to clean up in them.
+                if blockquote_depth > 1 || item_depth > 0 {
+                    continue;
+                }
+
+                // Include any indentation before the first `>`.
+                let line_start =
+                    text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                if !text[line_start..range.start].trim().is_empty() {
+                    continue;
+                }
+
+                rewritten.push_str(&text[copied_to..line_start]);
+                rewritten.push_str(&plain_blockquote(
+                    &text[line_start..range.end],
+                    config,
+                ));
+                copied_to = range.end;
+            }
+            End(TagEnd::BlockQuote(_)) => blockquote_depth -= 1,
+            _ => {}
+        }
+    }
+
+    rewritten.push_str(&text[copied_to..]);
+    rewritten
+}
+
+/// Normalize a single blockquote: no indentation before the `>`s, no empty
+/// lines before the content, and the plain-text form of any note or callout.
+fn plain_blockquote(blockquote: &str, config: &NoteConfig) -> String {
+    let mut lines = blockquote
+        .lines()
+        .map(|line| {
+            let unindented = line.trim_start_matches(' ');
+            if line.len() - unindented.len() < 4 {
+                unindented
+            } else {
+                line
             }
+        })
+        .skip_while(|line| line.trim_end() == ">")
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    if let Some(first) = lines.first_mut() {
+        let content = first
+            .strip_prefix("> ")
+            .or_else(|| first.strip_prefix('>'))
+            .unwrap_or(first);
+        let relabeled = Kind::of(content, config)
+            .and_then(|kind| kind.relabel(content, config));
+        if let Some(relabeled) = relabeled {
+            *first = format!("> {relabeled}");
         }
     }
 
-    let mut buf = String::new();
-    cmark(events.into_iter(), &mut buf).unwrap();
-    buf
+    let mut plain = lines.join("\n");
+    if blockquote.ends_with('\n') {
+        plain.push('\n');
+    }
+    plain
 }
 
 use State::*;
 
+/// The kinds of callout, each of which starts with its own prefix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Note,
+    Warning,
+    Tip,
+    Unsafe,
+}
+
+impl Kind {
+    /// Which kind of callout, if any, a blockquote starting with `text` is.
+    fn of(text: &str, config: &NoteConfig) -> Option<Kind> {
+        if config.strip_prefix(text).is_some() {
+            return Some(Kind::Note);
+        }
+
+        [Kind::Warning, Kind::Tip, Kind::Unsafe]
+            .into_iter()
+            .find(|kind| kind.split_prefix(text, config).is_some())
+    }
+
+    /// If `text` starts with one of the prefixes for this kind of callout, that
+    /// prefix and the rest of the text.
+    fn split_prefix<'t>(
+        self,
+        text: &'t str,
+        config: &'t NoteConfig,
+    ) -> Option<(&'t str, &'t str)> {
+        match self {
+            Kind::Note => config.prefixes.iter().find_map(|prefix| {
+                text.strip_prefix(prefix.as_str())
+                    .map(|rest| (prefix.as_str(), rest))
+            }),
+            Kind::Warning => config.warning.split_prefix(text),
+            Kind::Tip => config.tip.split_prefix(text),
+            Kind::Unsafe => config.unsafe_code.split_prefix(text),
+        }
+    }
+
+    fn label(self, config: &NoteConfig) -> Option<&str> {
+        match self {
+            Kind::Note => config.label.as_deref(),
+            Kind::Warning => config.warning.label.as_deref(),
+            Kind::Tip => config.tip.label.as_deref(),
+            Kind::Unsafe => config.unsafe_code.label.as_deref(),
+        }
+    }
+
+    /// The text with its prefix replaced by the configured label, if there is
+    /// one.
+    fn relabel(self, text: &str, config: &NoteConfig) -> Option<String> {
+        let label = self.label(config)?;
+        let (_, rest) = self.split_prefix(text, config)?;
+        Some(format!("{label} {rest}"))
+    }
+
+    /// The opening tag for a callout whose text starts with `text`.
+    fn opening_html(self, text: &str, config: &NoteConfig) -> String {
+        // The DPUB-ARIA roles: a tip is a `doc-tip`, while warnings and the
+        // cautions about unsafe code are both `doc-notice`s.
+        let (class, role) = match self {
+            // This predates the other kinds, and is kept exactly as it was.
+            Kind::Note => {
+                return String::from(
+                    r#"<section class="note" aria-role="note">"#,
+                )
+            }
+            Kind::Warning => ("warning", "doc-notice"),
+            Kind::Tip => ("tip", "doc-tip"),
+            Kind::Unsafe => ("unsafe", "doc-notice"),
+        };
+
+        let label = self
+            .label(config)
+            .or_else(|| {
+                self.split_prefix(text, config).map(|(prefix, _)| prefix)
+            })
+            .unwrap_or_default()
+            .trim()
+            .trim_end_matches(':');
+        // The label comes from the config, not from HTML in the chapter.
+        let mut escaped = String::new();
+        escape_html(&mut escaped, label)
+            .expect("writing to a String cannot fail");
+        format!(
+            r#"<section class="{class}" role="{role}" aria-label="{escaped}">"#
+        )
+    }
+}
+
 #[derive(Debug)]
 enum State<'e> {
     Default,
-    StartingBlockquote(Vec<Event<'e>>),
+    StartingBlockquote(Vec<Spanned<'e>>),
     InNote,
 }
 
@@ -1,9 +1,15 @@
 use super::*;
+use crate::config::{CalloutConfig, Rewrite};
 
 #[test]
 fn no_note() {
     let text = "Hello, world.\n\nThis is some text.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<p>Hello, world.</p>\n<p>This is some text.</p>\n"
@@ -13,7 +19,12 @@ fn no_note() {
 #[test]
 fn with_note() {
     let text = "> Note: This is some text.\n> It keeps going.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<p>Note: This is some text.\nIt keeps going.</p>\n</section>"
@@ -23,7 +34,12 @@ fn with_note() {
 #[test]
 fn regular_blockquote() {
     let text = "> This is some text.\n> It keeps going.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<blockquote>\n<p>This is some text.\nIt keeps going.</p>\n</blockquote>\n"
@@ -33,7 +49,12 @@ fn regular_blockquote() {
 #[test]
 fn combined() {
     let text = "> Note: This is some text.\n> It keeps going.\n\nThis is regular text.\n\n> This is a blockquote.\n";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<p>Note: This is some text.\nIt keeps going.</p>\n</section>\n<p>This is regular text.</p>\n<blockquote>\n<p>This is a blockquote.</p>\n</blockquote>\n"
@@ -43,7 +64,12 @@ fn combined() {
 #[test]
 fn blockquote_then_note() {
     let text = "> This is quoted.\n\n> Note: This is noted.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<blockquote>\n<p>This is quoted.</p>\n</blockquote>\n<section class=\"note\" aria-role=\"note\">\n<p>Note: This is noted.</p>\n</section>"
@@ -53,7 +79,12 @@ fn blockquote_then_note() {
 #[test]
 fn note_then_blockquote() {
     let text = "> Note: This is noted.\n\n> This is quoted.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<p>Note: This is noted.</p>\n</section>\n<blockquote>\n<p>This is quoted.</p>\n</blockquote>\n"
@@ -63,7 +94,12 @@ fn note_then_blockquote() {
 #[test]
 fn with_h1_note() {
     let text = "> # Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h1>Header</h1>\n<p>And then some note content.</p>\n</section>"
@@ -73,7 +109,12 @@ fn with_h1_note() {
 #[test]
 fn with_h2_note() {
     let text = "> ## Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h2>Header</h2>\n<p>And then some note content.</p>\n</section>"
@@ -83,7 +124,12 @@ fn with_h2_note() {
 #[test]
 fn with_h3_note() {
     let text = "> ### Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h3>Header</h3>\n<p>And then some note content.</p>\n</section>"
@@ -93,7 +139,12 @@ fn with_h3_note() {
 #[test]
 fn with_h4_note() {
     let text = "> #### Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h4>Header</h4>\n<p>And then some note content.</p>\n</section>"
@@ -103,7 +154,12 @@ fn with_h4_note() {
 #[test]
 fn with_h5_note() {
     let text = "> ##### Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h5>Header</h5>\n<p>And then some note content.</p>\n</section>"
@@ -113,7 +169,12 @@ fn with_h5_note() {
 #[test]
 fn with_h6_note() {
     let text = "> ###### Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h6>Header</h6>\n<p>And then some note content.</p>\n</section>"
@@ -124,7 +185,12 @@ fn with_h6_note() {
 fn h1_then_blockquote() {
     let text =
         "> # Header\n > And then some note content.\n\n> This is quoted.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<section class=\"note\" aria-role=\"note\">\n<h1>Header</h1>\n<p>And then some note content.</p>\n</section>\n<blockquote>\n<p>This is quoted.</p>\n</blockquote>\n"
@@ -135,7 +201,12 @@ fn h1_then_blockquote() {
 fn blockquote_then_h1_note() {
     let text =
         "> This is quoted.\n\n> # Header\n > And then some note content.";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<blockquote>\n<p>This is quoted.</p>\n</blockquote>\n<section class=\"note\" aria-role=\"note\">\n<h1>Header</h1>\n<p>And then some note content.</p>\n</section>"
@@ -145,7 +216,12 @@ fn blockquote_then_h1_note() {
 #[test]
 fn blockquote_with_strong() {
     let text = "> **Bold text in a paragraph.**";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<blockquote>\n<p><strong>Bold text in a paragraph.</strong></p>\n</blockquote>\n"
@@ -155,7 +231,12 @@ fn blockquote_with_strong() {
 #[test]
 fn normal_table() {
     let text = "| Header 1 | Header 2 |\n| -------- | -------- |\n| Text 123 | More 456 |";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
 
     assert_eq!(
         processed,
@@ -167,7 +248,12 @@ fn normal_table() {
 #[test]
 fn table_in_note() {
     let text = "> Note: table stuff.\n\n| Header 1 | Header 2 |\n| -------- | -------- |\n| Text 123 | More 456 |";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
 
     assert_eq!(
         processed,
@@ -179,7 +265,12 @@ fn table_in_note() {
 #[test]
 fn table_in_quote() {
     let text = "> A table.\n\n| Header 1 | Header 2 |\n| -------- | -------- |\n| Text 123 | More 456 |";
-    let processed = rewrite(text);
+    let processed = rewrite(
+        text,
+        Mode::Default,
+        Rewrite::Reserialize,
+        &NoteConfig::default(),
+    );
     assert_eq!(
         render_markdown(&processed),
         "<blockquote>\n<p>A table.</p>\n</blockquote>\n<table><thead><tr><th>Header 1</th><th>Header 2</th></tr></thead><tbody>\n<tr><td>Text 123</td><td>More 456</td></tr>\n</tbody></table>\n",
@@ -187,9 +278,321 @@ fn table_in_quote() {
     );
 }
 
+mod prefixes {
+    use super::*;
+
+    fn italian() -> NoteConfig {
+        NoteConfig {
+            prefixes: vec![String::from("Nota: "), String::from("Note: ")],
+            ..NoteConfig::default()
+        }
+    }
+
+    #[test]
+    fn localized_prefix() {
+        let text = "> Nota: Questo è un testo.";
+        let processed =
+            rewrite(text, Mode::Default, Rewrite::Reserialize, &italian());
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"note\" aria-role=\"note\">\n<p>Nota: Questo è un testo.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn any_prefix() {
+        let text = "> Note: This is some text.";
+        let processed =
+            rewrite(text, Mode::Default, Rewrite::Reserialize, &italian());
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"note\" aria-role=\"note\">\n<p>Note: This is some text.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn not_a_prefix() {
+        let text = "> Nota: Questo è un testo.";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<blockquote>\n<p>Nota: Questo è un testo.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn with_label() {
+        let config = NoteConfig {
+            label: Some(String::from("Nota:")),
+            ..italian()
+        };
+        let text = "> Note: This is some text.\n> It keeps going.";
+        let processed =
+            rewrite(text, Mode::Default, Rewrite::Reserialize, &config);
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"note\" aria-role=\"note\">\n<p>Nota: This is some text.\nIt keeps going.</p>\n</section>"
+        );
+    }
+}
+
+mod kinds {
+    use super::*;
+
+    #[test]
+    fn warning() {
+        let text = "> Warning: This is some text.\n> It keeps going.";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"warning\" role=\"doc-notice\" aria-label=\"Warning\">\n<p>Warning: This is some text.\nIt keeps going.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn tip() {
+        let text = "> Tip: This is some text.";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"tip\" role=\"doc-tip\" aria-label=\"Tip\">\n<p>Tip: This is some text.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn unsafe_callout() {
+        let text = "> Unsafe: This is some text.";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"unsafe\" role=\"doc-notice\" aria-label=\"Unsafe\">\n<p>Unsafe: This is some text.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn mixed() {
+        let text = "> Note: A note.\n\n> Tip: A tip.\n\n> Tipping point.\n";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"note\" aria-role=\"note\">\n<p>Note: A note.</p>\n</section>\n<section class=\"tip\" role=\"doc-tip\" aria-label=\"Tip\">\n<p>Tip: A tip.</p>\n</section>\n<blockquote>\n<p>Tipping point.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn label_only_applies_to_notes() {
+        let config = NoteConfig {
+            label: Some(String::from("Nota:")),
+            ..NoteConfig::default()
+        };
+        let text = "> Warning: This is some text.";
+        let processed =
+            rewrite(text, Mode::Default, Rewrite::Reserialize, &config);
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"warning\" role=\"doc-notice\" aria-label=\"Warning\">\n<p>Warning: This is some text.</p>\n</section>"
+        );
+    }
+
+    fn italian_warning() -> NoteConfig {
+        NoteConfig {
+            warning: CalloutConfig {
+                prefixes: vec![
+                    String::from("Attenzione: "),
+                    String::from("Warning: "),
+                ],
+                label: Some(String::from("Attenzione:")),
+            },
+            ..NoteConfig::default()
+        }
+    }
+
+    #[test]
+    fn localized_callout() {
+        let text = "> Warning: Questo è un testo.";
+        let processed = rewrite(
+            text,
+            Mode::Default,
+            Rewrite::Reserialize,
+            &italian_warning(),
+        );
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"warning\" role=\"doc-notice\" aria-label=\"Attenzione\">\n<p>Attenzione: Questo è un testo.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn label_is_escaped() {
+        let config = NoteConfig {
+            warning: CalloutConfig {
+                prefixes: vec![String::from("Warning: ")],
+                label: Some(String::from(r#"Say "when" & <stop>:"#)),
+            },
+            ..NoteConfig::default()
+        };
+        let processed = rewrite(
+            "> Warning: This is some text.",
+            Mode::Default,
+            Rewrite::Reserialize,
+            &config,
+        );
+        assert!(
+            processed.contains(
+                r#"aria-label="Say &quot;when&quot; &amp; &lt;stop&gt;">"#
+            ),
+            "{processed}"
+        );
+    }
+
+    #[test]
+    fn localized_prefix_without_label() {
+        let config = NoteConfig {
+            tip: CalloutConfig {
+                prefixes: vec![String::from("Suggerimento: ")],
+                label: None,
+            },
+            ..NoteConfig::default()
+        };
+        let text = "> Suggerimento: Questo è un testo.\n\n> Tip: Not a tip.";
+        let processed =
+            rewrite(text, Mode::Default, Rewrite::Reserialize, &config);
+        assert_eq!(
+            render_markdown(&processed),
+            "<section class=\"tip\" role=\"doc-tip\" aria-label=\"Suggerimento\">\n<p>Suggerimento: Questo è un testo.</p>\n</section>\n<blockquote>\n<p>Tip: Not a tip.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn localized_callout_in_simple_mode() {
+        let text = "> Warning: Questo è un testo.\n";
+        assert_eq!(
+            rewrite(
+                text,
+                Mode::Simple,
+                Rewrite::Reserialize,
+                &italian_warning()
+            ),
+            "> Attenzione: Questo è un testo.\n"
+        );
+    }
+}
+
+mod simple_mode {
+    use pulldown_cmark_to_cmark::cmark;
+
+    use super::*;
+
+    fn simple(text: &str) -> String {
+        rewrite(
+            text,
+            Mode::Simple,
+            Rewrite::Reserialize,
+            &NoteConfig::default(),
+        )
+    }
+
+    #[test]
+    fn note_is_unchanged() {
+        let text =
+            "Start.\n\n> Note: This is some text.\n> It keeps going.\n\nEnd.\n";
+        assert_eq!(simple(text), text);
+    }
+
+    #[test]
+    fn callouts_are_unchanged() {
+        let text =
+            "> Warning: A warning.\n\n> Tip: A tip.\n\n> Unsafe: Careful.";
+        assert_eq!(simple(text), text);
+    }
+
+    #[test]
+    fn cleans_up_round_tripped_blockquotes() {
+        let text = "Start\n\n >\n > Note: Hey.\n > More.\n\nWrap.";
+        assert_eq!(simple(text), "Start\n\n> Note: Hey.\n> More.\n\nWrap.");
+    }
+
+    #[test]
+    fn round_trips_through_cmark() {
+        let text =
+            "Start.\n\n> Note: This is some text.\n> It keeps going.\n\nWrap.";
+        let mut round_tripped = String::new();
+        cmark(crate::parser(text), &mut round_tripped).unwrap();
+        assert_eq!(simple(&round_tripped), text);
+    }
+
+    #[test]
+    fn with_label() {
+        let config = NoteConfig {
+            prefixes: vec![String::from("Nota: "), String::from("Note: ")],
+            label: Some(String::from("Nota:")),
+            ..NoteConfig::default()
+        };
+        let text = "> Note: This is some text.\n> It keeps going.";
+        assert_eq!(
+            rewrite(text, Mode::Simple, Rewrite::Reserialize, &config),
+            "> Nota: This is some text.\n> It keeps going."
+        );
+    }
+
+    #[test]
+    fn in_list_item_is_untouched() {
+        let text = "- An item.\n\n  > Note: A note in it.\n";
+        assert_eq!(simple(text), text);
+    }
+}
+
 fn render_markdown(text: &str) -> String {
     let parser = crate::parser(text);
     let mut buf = String::new();
     pulldown_cmark::html::push_html(&mut buf, parser);
     buf
 }
+
+#[test]
+fn splice_leaves_other_blockquotes_alone() {
+    let text =
+        "> This is some text.\nIt keeps going.\n\n> Note: This is a note.\n";
+    let processed =
+        rewrite(text, Mode::Default, Rewrite::Splice, &NoteConfig::default());
+    assert_eq!(
+        processed,
+        "> This is some text.\nIt keeps going.\n\n<section class=\"note\" aria-role=\"note\">\n\nNote: This is a note.\n\n</section>\n"
+    );
+}
+
+#[test]
+fn splice_callout() {
+    let text = "Some text.\n\n> ### Callout\n>\n> More text.\n\nThe end.\n";
+    let processed =
+        rewrite(text, Mode::Default, Rewrite::Splice, &NoteConfig::default());
+    assert_eq!(
+        processed,
+        "Some text.\n\n<section class=\"note\" aria-role=\"note\">\n\n### Callout\n\nMore text.\n\n</section>\n\nThe end.\n"
+    );
+}
@@ -18,3 +18,238 @@ mod note {
         assert!(cmd.is_err());
     }
 }
+
+mod listing {
+    use assert_cmd::Command;
+
+    #[test]
+    fn supports_html_renderer() {
+        let cmd = Command::cargo_bin("mdbook-trpl-listing")
+            .unwrap()
+            .args(["supports", "html"])
+            .ok();
+        assert!(cmd.is_ok());
+    }
+
+    #[test]
+    fn check_reports_mismatches() {
+        let output = Command::cargo_bin("mdbook-trpl-listing")
+            .unwrap()
+            .args(["check", "tests/integration/fixtures/listing-check"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(
+            "src/chapter_1.md:11:1: error: `file-name` 'src/lib.rs' does not match the included file '../listings/ch01/listing-01-03/src/main.rs'\n"
+        ));
+        assert!(stderr.contains(
+            "src/chapter_1.md:11:1: error: `number` '1-2' does not match the included listing directory 'listing-01-03'\n"
+        ));
+    }
+}
+
+mod trpl {
+    use assert_cmd::Command;
+
+    #[test]
+    fn supports_html_renderer() {
+        let cmd = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .args(["supports", "html"])
+            .ok();
+        assert!(cmd.is_ok());
+    }
+
+    #[test]
+    fn errors_for_other_renderers() {
+        let cmd = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .args(["supports", "total-nonsense"])
+            .ok();
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn runs_all_preprocessors() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .write_stdin(input(r#"{ "output-mode": "simple" }"#))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("# Hello, world"), "{stdout}");
+        assert!(stdout.contains("> Note: This is a note."), "{stdout}");
+        assert!(stdout.contains("Listing 1-1: A listing"), "{stdout}");
+    }
+
+    #[test]
+    fn runs_one_preprocessor() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .arg("note")
+            .write_stdin(input("{}"))
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(r#"<section class=\"note\""#), "{stdout}");
+        assert!(stdout.contains("<Listing number"), "{stdout}");
+    }
+
+    #[test]
+    fn check_reports_mismatches() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .args(["check", "tests/integration/fixtures/listing-check"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Found 2 listing mismatch(es)"), "{stderr}");
+    }
+
+    #[test]
+    fn check_reports_mismatches_as_json() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .args([
+                "check",
+                "--json",
+                "tests/integration/fixtures/listing-check",
+            ])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let mismatches: serde_json::Value =
+            serde_json::from_slice(&output.stdout).unwrap();
+        let mismatches = mismatches.as_array().unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0]["path"], "src/chapter_1.md");
+        assert_eq!(mismatches[0]["line"], 11);
+        assert_eq!(mismatches[0]["column"], 1);
+    }
+
+    #[test]
+    fn reports_errors_one_per_line() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .write_stdin(input_with("{}", UNCLOSED))
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr
+                .starts_with("src/chapter_1.md:3:1: error: Unclosed listing\n"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("Found 1 error(s)"), "{stderr}");
+    }
+
+    #[test]
+    fn reports_errors_as_json() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .env("TRPL_DIAGNOSTICS", "json")
+            .write_stdin(input_with("{}", UNCLOSED))
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let json = stderr.lines().next().unwrap();
+        let errors: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(errors[0]["path"], "src/chapter_1.md");
+        assert_eq!(errors[0]["line"], 3);
+        assert_eq!(errors[0]["severity"], "error");
+        assert_eq!(errors[0]["message"], "Unclosed listing");
+    }
+
+    #[test]
+    fn reports_warnings_with_errors_as_one_array() {
+        let output = Command::cargo_bin("mdbook-trpl")
+            .unwrap()
+            .env("TRPL_DIAGNOSTICS", "json")
+            .write_stdin(input_with(
+                "{}",
+                &format!("![](img/ferris.svg)\n\n{UNCLOSED}"),
+            ))
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let arrays = stderr
+            .lines()
+            .filter(|line| line.starts_with('['))
+            .collect::<Vec<_>>();
+        assert_eq!(arrays.len(), 1, "{stderr}");
+        let diagnostics: serde_json::Value =
+            serde_json::from_str(arrays[0]).unwrap();
+        assert_eq!(diagnostics[0]["severity"], "warning");
+        assert_eq!(diagnostics[1]["severity"], "error");
+        assert!(stderr.contains("Found 1 error(s)"), "{stderr}");
+    }
+
+    const UNCLOSED: &str = "# Hello\n\n<Listing number=\"1-1\">\n";
+
+    /// The input mdbook gives preprocessors, for a book with one chapter and
+    /// the given config for both the `trpl` and `trpl-note` preprocessors.
+    fn input(config: &str) -> String {
+        input_with(
+            config,
+            "# Hello, *world*\n\n> Note: This is a note.\n\n<Listing number=\"1-1\" caption=\"A listing\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+        )
+    }
+
+    /// The same input as [`input`], with `content` for the chapter.
+    fn input_with(config: &str, content: &str) -> String {
+        let content = serde_json::to_string(content).unwrap();
+        format!(
+            r#"[
+                {{
+                    "root": "/path/to/book",
+                    "config": {{
+                        "book": {{
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        }},
+                        "preprocessor": {{
+                            "trpl": {config},
+                            "trpl-note": {config}
+                        }}
+                    }},
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                }},
+                {{
+                    "sections": [
+                        {{
+                            "Chapter": {{
+                                "name": "Chapter 1",
+                                "content": {content},
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }}
+                        }}
+                    ],
+                    "__non_exhaustive": null
+                }}
+            ]"#
+        )
+    }
+}
@@ -0,0 +1,229 @@
+//! Check the diagrams in `dot/` against the SVGs rendered from them in
+//! `src/img`:
+//!
+//! - Every `dot/<name>.dot` must have a `src/img/<name>.svg`.
+//! - Every `src/img/trpl*.svg` must have a `dot/<name>.dot` source.
+//! - The text in each SVG must match the labels in its source.
+//!
+//! The SVGs are rendered with Graphviz and then adjusted by hand (their size,
+//! for one), so they cannot be compared byte for byte with a fresh render.
+//! Instead, this renders each source with `dot -Tsvg` and compares the text in
+//! the two SVGs, so a label which changed in a `.dot` file, e.g. when
+//! translating it, but not in its SVG shows up as a mismatch. That needs
+//! Graphviz's `dot` on the `PATH`, and it is an error for it to be missing.
+//! Pass `--skip-render` to run only the first two checks, e.g. locally without
+//! Graphviz.
+//!
+//! Usage: `cargo run --bin check_diagrams -- [--skip-render] [<book-dir>]`
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use docopt::Docopt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    let root = args
+        .arg_book_dir
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    let dot_dir = root.join("dot");
+    let img_dir = root.join("src").join("img");
+
+    let sources = files_with_extension(&dot_dir, "dot")?;
+    let svgs = files_with_extension(&img_dir, "svg")?;
+
+    let mut problems = vec![];
+    for (name, source) in &sources {
+        if !svgs.contains_key(name) {
+            problems.push(format!(
+                "{}: no rendered SVG at {}",
+                source.display(),
+                img_dir.join(format!("{name}.svg")).display()
+            ));
+        }
+    }
+    for (name, svg) in &svgs {
+        if name.starts_with("trpl") && !sources.contains_key(name) {
+            problems.push(format!(
+                "{}: no source at {}",
+                svg.display(),
+                dot_dir.join(format!("{name}.dot")).display()
+            ));
+        }
+    }
+
+    if !args.flag_skip_render {
+        if Command::new("dot").arg("-V").output().is_err() {
+            return Err("Graphviz's `dot` is not installed, so the SVGs' labels \
+                cannot be checked; install it, or pass `--skip-render` to skip \
+                that check"
+                .into());
+        }
+
+        for (name, source) in &sources {
+            let Some(svg) = svgs.get(name) else {
+                continue;
+            };
+
+            let rendered = render(source)?;
+            let committed = fs::read_to_string(svg).map_err(|e| {
+                format!("Could not read '{}': {e}", svg.display())
+            })?;
+            if let Some(mismatch) =
+                compare_labels(&text_of(&rendered), &text_of(&committed))
+            {
+                problems.push(format!(
+                    "{}: does not match {}: {mismatch}",
+                    svg.display(),
+                    source.display()
+                ));
+            }
+        }
+    }
+
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Found {} problem(s) with diagrams", problems.len()).into())
+    }
+}
+
+const USAGE: &str = "
+Check the diagrams in dot/ against the SVGs rendered from them in src/img.
+
+Usage:
+  check_diagrams [--skip-render] [<book-dir>]
+  check_diagrams (-h | --help)
+Options:
+  -h --help      Show this screen.
+  --skip-render  Do not render the sources to compare their labels.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_skip_render: bool,
+    arg_book_dir: Option<String>,
+}
+
+/// The files in `dir` with the given extension, by their names without it.
+fn files_with_extension(
+    dir: &Path,
+    extension: &str,
+) -> Result<BTreeMap<String, PathBuf>, Box<dyn Error>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir)
+        .map_err(|e| format!("Could not read '{}': {e}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == extension) {
+            if let Some(name) = path.file_stem() {
+                files.insert(name.to_string_lossy().into_owned(), path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn render(source: &Path) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("dot").arg("-Tsvg").arg(source).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "Could not render '{}': {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// All the text drawn in an SVG, sorted, ignoring where it is drawn.
+fn text_of(svg: &str) -> Vec<String> {
+    let mut text = TEXT
+        .captures_iter(svg)
+        .map(|captures| captures[1].trim().to_string())
+        .collect::<Vec<_>>();
+    text.sort();
+    text
+}
+
+/// Describe how the `actual` text differs from the `expected` text, if it does.
+/// Both must be sorted.
+fn compare_labels(expected: &[String], actual: &[String]) -> Option<String> {
+    let missing = difference(expected, actual);
+    let unexpected = difference(actual, expected);
+    match (missing.is_empty(), unexpected.is_empty()) {
+        (true, true) => None,
+        (false, true) => Some(format!("missing {missing:?}")),
+        (true, false) => Some(format!("unexpected {unexpected:?}")),
+        (false, false) => {
+            Some(format!("missing {missing:?}, unexpected {unexpected:?}"))
+        }
+    }
+}
+
+/// The items in `a` which are not in `b`, counting duplicates. Both must be
+/// sorted.
+fn difference<'a>(a: &'a [String], b: &[String]) -> Vec<&'a str> {
+    let mut b = b.iter().peekable();
+    let mut only_a = vec![];
+    for item in a {
+        while b.next_if(|other| *other < item).is_some() {}
+        if b.next_if(|other| *other == item).is_none() {
+            only_a.push(item.as_str());
+        }
+    }
+    only_a
+}
+
+lazy_static! {
+    static ref TEXT: Regex = Regex::new(r"<text[^>]*>([^<]*)</text>").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_sorted_without_positions() {
+        let svg = r#"<g>
+<text text-anchor="start" x="45.7759" y="-129.8">s1</text>
+<text text-anchor="start" x="18.8413" y="-109.8">name</text>
+<text text-anchor="start" x="74.5" y="-69.8">5</text>
+<text text-anchor="start" x="74.5" y="-49.8">5</text>
+</g>"#;
+        assert_eq!(text_of(svg), vec!["5", "5", "name", "s1"]);
+    }
+
+    #[test]
+    fn matching_labels() {
+        let labels = text_of("<text>name</text><text>value</text>");
+        assert_eq!(compare_labels(&labels, &labels), None);
+    }
+
+    #[test]
+    fn mismatched_labels() {
+        let expected = text_of("<text>5</text><text>5</text><text>nome</text>");
+        let actual = text_of("<text>5</text><text>name</text>");
+        assert_eq!(
+            compare_labels(&expected, &actual),
+            Some(String::from(
+                r#"missing ["5", "nome"], unexpected ["name"]"#
+            ))
+        );
+    }
+}
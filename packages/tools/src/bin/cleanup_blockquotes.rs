@@ -5,6 +5,9 @@
 //!
 //! Note: later versions of `pulldown-cmark-to-cmark` will likely fix this, so
 //! check when upgrading it if it is still necessary!
+//!
+//! The `mdbook-trpl` preprocessors do not need this with `rewrite = "splice"`,
+//! which leaves blockquotes other than notes exactly as they were written.
 
 use std::io::{self, Read};
 
@@ -99,7 +99,7 @@ where
 }
 
 fn is_file_of_interest(path: &path::Path) -> bool {
-    path.extension().map_or(false, |ext| ext == "md")
+    path.extension().is_some_and(|ext| ext == "md")
 }
 
 fn is_line_of_interest(line: &str) -> bool {
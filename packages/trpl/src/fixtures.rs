@@ -0,0 +1,153 @@
+//! Canned responses for [`get`], so that code which fetches web pages, like the
+//! examples in chapter 17, can run without a network: in CI, say, or offline.
+//!
+//! This is only available with the `fixtures` feature. Fixtures come from an
+//! in-memory map of URLs to response bodies, from a directory of files, or
+//! both:
+//!
+//! ```
+//! let _fixtures = trpl::fixtures::scope();
+//! trpl::fixtures::insert("https://example.com/", "<title>Example</title>");
+//!
+//! let title = trpl::block_on(async {
+//!     let text = trpl::get("https://example.com/").await.text().await;
+//!     trpl::Html::parse(&text).select_first("title").map(|t| t.inner_html())
+//! });
+//! assert_eq!(title, Some(String::from("Example")));
+//! ```
+//!
+//! Once there are any fixtures, [`get`] serves *every* request from them, and
+//! fails for a URL with no fixture instead of going to the network. Without
+//! any, it goes to the network as usual.
+//!
+//! The fixtures are shared by the whole program, so tests, which run in
+//! parallel, should each hold a [`scope`] while they use them, as above.
+//!
+//! [`get`]: crate::get
+
+use std::{
+    collections::BTreeMap,
+    env, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// The environment variable which sets a directory to serve fixtures from, as
+/// if it were passed to [`serve_dir`]. It lets programs which call [`get`] run
+/// offline without any changes, e.g.:
+///
+/// ```console
+/// $ TRPL_FIXTURES=fixtures cargo run --features trpl/fixtures
+/// ```
+///
+/// [`get`]: crate::get
+pub const DIR_VAR: &str = "TRPL_FIXTURES";
+
+static FIXTURES: Mutex<Fixtures> = Mutex::new(Fixtures {
+    dir: None,
+    responses: BTreeMap::new(),
+});
+
+struct Fixtures {
+    dir: Option<PathBuf>,
+    responses: BTreeMap<String, String>,
+}
+
+/// Held by the current [`Scope`], if any.
+static SCOPE: Mutex<()> = Mutex::new(());
+
+/// Start using the fixtures without any other [`Scope`] seeing them, waiting
+/// for the current one to end first. Any fixtures from before are cleared, and
+/// so are the ones added during the scope when it ends.
+pub fn scope() -> Scope {
+    // A test which panics, even on purpose, poisons the lock, but its scope
+    // still cleared up after it.
+    let guard = SCOPE.lock().unwrap_or_else(PoisonError::into_inner);
+    clear();
+    Scope { _guard: guard }
+}
+
+/// Exclusive use of the fixtures, from [`scope`] until it is dropped.
+#[must_use = "the scope ends as soon as it is dropped"]
+pub struct Scope {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        clear();
+    }
+}
+
+/// Serve requests from the files in `dir`.
+///
+/// The file for a URL is its host and path within `dir`, so the body for
+/// `https://www.rust-lang.org/learn` is in `www.rust-lang.org/learn`. A URL
+/// whose path ends in `/` gets `index.html` in that directory. Any query or
+/// fragment is ignored.
+///
+/// Responses added with [`insert`] take precedence over the files.
+pub fn serve_dir(dir: impl Into<PathBuf>) {
+    FIXTURES.lock().unwrap().dir = Some(dir.into());
+}
+
+/// Respond to a request for exactly `url` with `body`.
+pub fn insert(url: impl Into<String>, body: impl Into<String>) {
+    FIXTURES
+        .lock()
+        .unwrap()
+        .responses
+        .insert(url.into(), body.into());
+}
+
+/// Remove all the fixtures, including the directory, so that [`get`] goes to
+/// the network again (unless [`DIR_VAR`] is set).
+///
+/// [`get`]: crate::get
+pub fn clear() {
+    let mut fixtures = FIXTURES.lock().unwrap();
+    fixtures.dir = None;
+    fixtures.responses.clear();
+}
+
+/// The body of the fixture for `url`, or `None` if there are no fixtures at
+/// all and the request should go to the network.
+pub(crate) async fn lookup(url: &str) -> Option<io::Result<String>> {
+    let dir = {
+        let fixtures = FIXTURES.lock().unwrap();
+        if let Some(body) = fixtures.responses.get(url) {
+            return Some(Ok(body.clone()));
+        }
+
+        match (&fixtures.dir, env::var_os(DIR_VAR)) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(dir)) => PathBuf::from(dir),
+            (None, None) if fixtures.responses.is_empty() => return None,
+            (None, None) => return Some(Err(not_found(url))),
+        }
+    };
+
+    let Some(path) = path_for(&dir, url) else {
+        return Some(Err(not_found(url)));
+    };
+    Some(tokio::fs::read_to_string(path).await.map_err(|error| {
+        match error.kind() {
+            io::ErrorKind::NotFound => not_found(url),
+            _ => error,
+        }
+    }))
+}
+
+fn path_for(dir: &Path, url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut path = dir.join(url.host_str()?);
+    path.extend(url.path_segments()?.filter(|segment| !segment.is_empty()));
+    if url.path().ends_with('/') {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+fn not_found(url: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No fixture for {url}"))
+}
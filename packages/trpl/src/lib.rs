@@ -14,8 +14,11 @@
 //!    never be broken by upstream changes, e.g. if Tokio does a breaking 2.0
 //!    release at some point.
 
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+
 // For direct use within the `trpl` crate, *not* re-exported.
-use std::{future::Future, pin::pin};
+use std::{fmt, future::Future, pin::pin, time::Duration};
 
 use futures::future;
 
@@ -43,7 +46,7 @@ pub use tokio::{
         unbounded_channel as channel,
     },
     task::{JoinHandle, spawn as spawn_task, yield_now},
-    time::{interval, sleep},
+    time::{error::Elapsed, interval, sleep},
 };
 
 pub use tokio_stream::{
@@ -51,6 +54,8 @@ pub use tokio_stream::{
     wrappers::{IntervalStream, UnboundedReceiverStream as ReceiverStream},
 };
 
+pub use tokio_util::sync::CancellationToken;
+
 /// Run a single future to completion on a bespoke Tokio `Runtime`.
 ///
 /// Every time you call this, a new instance of `tokio::runtime::Runtime` will
@@ -62,8 +67,44 @@ pub use tokio_stream::{
 ///
 /// - Not *that* far off from what Tokio itself does under the hood in its own
 ///   `tokio::main` macro for supporting `async fn main`.
+///
+/// With the `deterministic` feature enabled, this instead does exactly what
+/// `block_on_deterministic` does.
 pub fn block_on<F: Future>(future: F) -> F::Output {
-    let rt = Runtime::new().unwrap();
+    #[cfg(feature = "deterministic")]
+    {
+        block_on_deterministic(future)
+    }
+
+    #[cfg(not(feature = "deterministic"))]
+    {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(future)
+    }
+}
+
+/// Run a single future to completion on a bespoke Tokio `Runtime` which gives
+/// the same result every time.
+///
+/// Unlike [`block_on`], the runtime runs every task on the current thread, so
+/// tasks always take turns in the same order, and its clock starts paused:
+/// whenever every task is waiting, it jumps straight ahead to the next timer.
+/// That makes the interleaving of futures which [`sleep`] for different amounts
+/// of time the same from one run to the next, and it means they do not actually
+/// wait, either.
+///
+/// Since the clock does not wait for anything but timers, a timer can fire
+/// before real I/O which should have finished first, like a [`get`] request.
+///
+/// This is only available with the `deterministic` feature, since pausing the
+/// clock needs Tokio's `test-util` feature.
+#[cfg(feature = "deterministic")]
+pub fn block_on_deterministic<F: Future>(future: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap();
     rt.block_on(future)
 }
 
@@ -115,23 +156,88 @@ where
     select(f1, f2).await
 }
 
+/// Run a future, giving up on it if it takes longer than `duration`.
+///
+/// Returns the future's output, or [`Elapsed`] if the time ran out first, in
+/// which case the future is dropped. This is a thin wrapper around
+/// [`tokio::time::timeout`]. Unlike that function, it does not start the timer
+/// until it is first awaited, so it can be passed straight to [`block_on`]:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let result = trpl::block_on(trpl::timeout(
+///     Duration::from_millis(10),
+///     async { trpl::sleep(Duration::from_secs(10)).await },
+/// ));
+/// assert!(result.is_err());
+/// ```
+pub async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    tokio::time::timeout(duration, future).await
+}
+
 /// Fetch data from a URL. For more convenient use in _The Rust Programming
 /// Language_, panics instead of returning a [`Result`] if the request fails.
+/// Use [`try_get`] to handle the failure instead.
+///
+/// With the `fixtures` feature, this can serve canned responses instead of
+/// going to the network: see the `fixtures` module.
 pub async fn get(url: &str) -> Response {
-    Response(reqwest::get(url).await.unwrap())
+    try_get(url).await.unwrap_or_else(|error| panic!("{error}"))
+}
+
+/// Fetch data from a URL, returning an [`Error`] if the request fails, e.g.
+/// because the URL is invalid or the server cannot be reached.
+pub async fn try_get(url: &str) -> Result<Response, Error> {
+    #[cfg(feature = "fixtures")]
+    if let Some(body) = fixtures::lookup(url).await {
+        return body
+            .map(|text| Response(Body::Fixture(text)))
+            .map_err(|error| Error(ErrorKind::Fixture(error)));
+    }
+
+    reqwest::get(url)
+        .await
+        .map(|response| Response(Body::Network(response)))
+        .map_err(|error| Error(ErrorKind::Request(error)))
 }
 
 /// A thin wrapper around [`reqwest::Response`] to make the demos in _The Rust
 /// Programming Language_ substantially nicer to use.
-pub struct Response(reqwest::Response);
+pub struct Response(Body);
+
+enum Body {
+    Network(reqwest::Response),
+    #[cfg(feature = "fixtures")]
+    Fixture(String),
+}
 
 impl Response {
     /// Get the full response text.
     ///
     /// If the response cannot be deserialized, this panics instead of returning
-    /// a [`Result`] (for convenience in the demo).
+    /// a [`Result`] (for convenience in the demo). Use [`Response::try_text`]
+    /// to handle the failure instead.
     pub async fn text(self) -> String {
-        self.0.text().await.unwrap()
+        self.try_text()
+            .await
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Get the full response text, returning an [`Error`] if the response
+    /// cannot be deserialized.
+    pub async fn try_text(self) -> Result<String, Error> {
+        match self.0 {
+            Body::Network(response) => response
+                .text()
+                .await
+                .map_err(|error| Error(ErrorKind::Request(error))),
+            #[cfg(feature = "fixtures")]
+            Body::Fixture(text) => Ok(text),
+        }
     }
 }
 
@@ -152,16 +258,135 @@ impl Html {
         }
     }
 
-    /// Get the first item in the document matching a string selector. Returns
-    /// Some()
+    /// Get the first item in the document matching a string selector, or
+    /// `None` if nothing matches.
+    ///
+    /// If the selector is not a valid CSS selector, panics rather than
+    /// returning a [`Result`] for convenience. Use [`Html::try_select_first`]
+    /// to handle an invalid selector instead.
+    pub fn select_first<'a>(&'a self, selector: &str) -> Option<Element<'a>> {
+        self.try_select_first(selector)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Get the first item in the document matching a string selector, or an
+    /// [`Error`] if the selector is not a valid CSS selector.
+    pub fn try_select_first<'a>(
+        &'a self,
+        selector: &str,
+    ) -> Result<Option<Element<'a>>, Error> {
+        Ok(self.try_select_all(selector)?.next())
+    }
+
+    /// Get every item in the document matching a string selector, in the order
+    /// they appear in the document.
     ///
     /// If the selector is not a valid CSS selector, panics rather than
-    /// returning a [`Result`] for convenience.
-    pub fn select_first<'a>(
+    /// returning a [`Result`] for convenience. Use [`Html::try_select_all`] to
+    /// handle an invalid selector instead.
+    pub fn select_all<'a>(
+        &'a self,
+        selector: &str,
+    ) -> impl Iterator<Item = Element<'a>> + use<'a> {
+        self.try_select_all(selector)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Get every item in the document matching a string selector, in the order
+    /// they appear in the document, or an [`Error`] if the selector is not a
+    /// valid CSS selector.
+    pub fn try_select_all<'a>(
         &'a self,
-        selector: &'a str,
-    ) -> Option<scraper::ElementRef<'a>> {
-        let selector = scraper::Selector::parse(selector).unwrap();
-        self.inner.select(&selector).nth(0)
+        selector: &str,
+    ) -> Result<impl Iterator<Item = Element<'a>> + use<'a>, Error> {
+        let selector = parse_selector(selector)?;
+        Ok(self
+            .inner
+            .tree
+            .root()
+            .descendants()
+            .filter_map(scraper::ElementRef::wrap)
+            .filter(move |element| selector.matches(element))
+            .map(Element))
+    }
+}
+
+/// An element in an [`Html`] document, as returned by [`Html::select_first`]
+/// and [`Html::select_all`].
+///
+/// This is a thin wrapper around [`scraper::ElementRef`], which keeps the
+/// exported API surface simpler.
+#[derive(Clone, Copy, Debug)]
+pub struct Element<'a>(scraper::ElementRef<'a>);
+
+impl<'a> Element<'a> {
+    /// The text inside the element, including the text of every element nested
+    /// in it, without any markup.
+    pub fn text(&self) -> String {
+        self.0.text().collect()
+    }
+
+    /// The value of the attribute called `name`, or `None` if the element does
+    /// not have it.
+    pub fn attr(&self, name: &str) -> Option<&'a str> {
+        self.0.value().attr(name)
+    }
+
+    /// The HTML inside the element, not including the element's own tag.
+    pub fn inner_html(&self) -> String {
+        self.0.inner_html()
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<scraper::Selector, Error> {
+    scraper::Selector::parse(selector).map_err(|error| {
+        Error(ErrorKind::Selector {
+            selector: selector.to_string(),
+            message: error.to_string(),
+        })
+    })
+}
+
+/// The error returned by the fallible helpers: [`try_get`],
+/// [`Response::try_text`], [`Html::try_select_first`], and
+/// [`Html::try_select_all`].
+///
+/// It only implements [`Display`](fmt::Display) and [`std::error::Error`], so
+/// that it does not tie the API to the crates `trpl` uses internally.
+#[derive(Debug)]
+pub struct Error(ErrorKind);
+
+#[derive(Debug)]
+enum ErrorKind {
+    Request(reqwest::Error),
+    #[cfg(feature = "fixtures")]
+    Fixture(std::io::Error),
+    Selector {
+        selector: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            ErrorKind::Request(error) => write!(f, "request failed: {error}"),
+            #[cfg(feature = "fixtures")]
+            ErrorKind::Fixture(error) => write!(f, "request failed: {error}"),
+            ErrorKind::Selector { selector, message } => {
+                write!(f, "invalid CSS selector `{selector}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.0 {
+            ErrorKind::Request(error) => Some(error),
+            #[cfg(feature = "fixtures")]
+            ErrorKind::Fixture(error) => Some(error),
+            ErrorKind::Selector { .. } => None,
+        }
     }
 }
@@ -35,6 +35,70 @@ fn re_exported_block_on_works() {
     assert_eq!(val, "Hello");
 }
 
+#[cfg(feature = "deterministic")]
+mod block_on_deterministic {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Two tasks which print at different intervals, like the listings in
+    /// chapter 17, and the order they printed in.
+    async fn interleaved() -> Vec<String> {
+        let (tx, mut rx) = trpl::channel();
+
+        let tx1 = tx.clone();
+        let a = trpl::spawn_task(async move {
+            for i in 1..=3 {
+                tx1.send(format!("a{i}")).unwrap();
+                trpl::sleep(Duration::from_millis(300)).await;
+            }
+        });
+        let b = trpl::spawn_task(async move {
+            for i in 1..=3 {
+                tx.send(format!("b{i}")).unwrap();
+                trpl::sleep(Duration::from_millis(500)).await;
+            }
+        });
+        a.await.unwrap();
+        b.await.unwrap();
+
+        let mut order = vec![];
+        while let Some(message) = rx.recv().await {
+            order.push(message);
+        }
+        order
+    }
+
+    #[test]
+    fn is_deterministic() {
+        for _ in 0..10 {
+            let order = trpl::block_on_deterministic(interleaved());
+            assert_eq!(order, ["a1", "b1", "a2", "b2", "a3", "b3"]);
+        }
+    }
+
+    #[test]
+    fn does_not_actually_wait() {
+        let start = Instant::now();
+        let val = trpl::block_on_deterministic(async {
+            trpl::sleep(Duration::from_secs(60 * 60)).await;
+            "Done!"
+        });
+        assert_eq!(val, "Done!");
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn select() {
+        let val = trpl::block_on_deterministic(async {
+            let slow = trpl::sleep(Duration::from_millis(1_000));
+            let fast = trpl::sleep(Duration::from_millis(1));
+            trpl::select(slow, fast).await
+        });
+        assert!(matches!(val, Either::Right(())));
+    }
+}
+
 #[test]
 fn re_exported_spawn_works() {
     let result = trpl::block_on(async {
@@ -275,3 +339,293 @@ fn re_exported_html() {
     let p = doc.select_first("p").map(|el| el.inner_html());
     assert_eq!(p, Some(String::from("Hello!")));
 }
+
+mod html {
+    use trpl::Html;
+
+    const PAGE: &str = r#"<html>
+        <head><title>Links</title></head>
+        <body>
+            <h1>Some <em>useful</em> links</h1>
+            <ul>
+                <li><a href="https://www.rust-lang.org">Rust</a></li>
+                <li><a href="https://doc.rust-lang.org/book">The <em>Book</em></a></li>
+                <li><a>Nowhere</a></li>
+            </ul>
+        </body>
+    </html>"#;
+
+    #[test]
+    fn select_all() {
+        let doc = Html::parse(PAGE);
+
+        let links: Vec<_> = doc
+            .select_all("a")
+            .map(|link| (link.text(), link.attr("href")))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (String::from("Rust"), Some("https://www.rust-lang.org")),
+                (
+                    String::from("The Book"),
+                    Some("https://doc.rust-lang.org/book")
+                ),
+                (String::from("Nowhere"), None),
+            ]
+        );
+        assert_eq!(doc.select_all("h2").count(), 0);
+    }
+
+    #[test]
+    fn element() {
+        let doc = Html::parse(PAGE);
+
+        let heading = doc.select_first("h1").unwrap();
+        assert_eq!(heading.text(), "Some useful links");
+        assert_eq!(heading.inner_html(), "Some <em>useful</em> links");
+        assert_eq!(heading.attr("id"), None);
+    }
+
+    #[test]
+    fn try_select_all() {
+        let doc = Html::parse(PAGE);
+
+        assert_eq!(doc.try_select_all("li > a[href]").unwrap().count(), 2);
+        assert!(doc.try_select_all("li >").is_err());
+    }
+
+    #[test]
+    fn selector_outlived_by_results() {
+        let doc = Html::parse(PAGE);
+
+        let tag = "a";
+        let links = doc.select_all(&format!("li > {tag}"));
+        assert_eq!(links.count(), 3);
+
+        let selector = String::from("a[href]");
+        let links = doc.try_select_all(&selector).unwrap();
+        drop(selector);
+        assert_eq!(links.count(), 2);
+    }
+}
+
+mod fallible {
+    use trpl::Html;
+
+    #[test]
+    fn try_select_first() {
+        let doc = Html::parse("<p>Hello!</p>");
+
+        let p = doc.try_select_first("p").unwrap().map(|el| el.inner_html());
+        assert_eq!(p, Some(String::from("Hello!")));
+        assert!(doc.try_select_first("h1").unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_selector() {
+        let doc = Html::parse("<p>Hello!</p>");
+
+        let error = doc.try_select_first("p[").unwrap_err();
+        assert!(
+            error.to_string().starts_with("invalid CSS selector `p[`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid CSS selector")]
+    fn select_first_panics() {
+        Html::parse("<p>Hello!</p>").select_first("p[");
+    }
+
+    #[test]
+    fn invalid_url() {
+        let result = trpl::block_on(trpl::try_get("not a url"));
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(feature = "fixtures")]
+mod fixtures {
+    use trpl::Html;
+
+    fn serve_fixtures() {
+        trpl::fixtures::serve_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/integration/fixtures"
+        ));
+    }
+
+    fn title(url: &str) -> Option<String> {
+        trpl::block_on(async {
+            let text = trpl::get(url).await.text().await;
+            Html::parse(&text)
+                .select_first("title")
+                .map(|title| title.inner_html())
+        })
+    }
+
+    #[test]
+    fn from_memory() {
+        let _fixtures = trpl::fixtures::scope();
+        trpl::fixtures::insert(
+            "https://memory.example.com/",
+            "<html><head><title>In memory</title></head></html>",
+        );
+
+        assert_eq!(
+            title("https://memory.example.com/"),
+            Some(String::from("In memory"))
+        );
+    }
+
+    #[test]
+    fn from_dir() {
+        let _fixtures = trpl::fixtures::scope();
+        serve_fixtures();
+
+        assert_eq!(
+            title("https://example.com/"),
+            Some(String::from("Example Domain"))
+        );
+        assert_eq!(
+            title("https://example.com/articles/async.html?page=1#top"),
+            Some(String::from("Articles"))
+        );
+    }
+
+    #[test]
+    fn memory_takes_precedence() {
+        let _fixtures = trpl::fixtures::scope();
+        serve_fixtures();
+        trpl::fixtures::insert(
+            "https://example.com/articles/",
+            "<title>Overridden</title>",
+        );
+
+        assert_eq!(
+            title("https://example.com/articles/"),
+            Some(String::from("Overridden"))
+        );
+    }
+
+    #[test]
+    fn try_missing() {
+        let _fixtures = trpl::fixtures::scope();
+        serve_fixtures();
+
+        let error = trpl::block_on(trpl::try_get("https://example.com/nope"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "request failed: No fixture for https://example.com/nope"
+        );
+    }
+
+    #[test]
+    fn clear() {
+        let _fixtures = trpl::fixtures::scope();
+        serve_fixtures();
+        trpl::fixtures::insert(
+            "https://memory.example.com/",
+            "<title>Gone</title>",
+        );
+        trpl::fixtures::clear();
+        trpl::fixtures::insert(
+            "https://other.example.com/",
+            "<title>Other</title>",
+        );
+
+        for url in ["https://memory.example.com/", "https://example.com/"] {
+            let error = trpl::block_on(trpl::try_get(url)).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                format!("request failed: No fixture for {url}")
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No fixture for https://example.com/missing")]
+    fn missing() {
+        let _fixtures = trpl::fixtures::scope();
+        serve_fixtures();
+        title("https://example.com/missing");
+    }
+}
+
+mod timeout {
+    use std::time::Duration;
+
+    #[test]
+    fn completes() {
+        let result =
+            trpl::block_on(trpl::timeout(Duration::from_secs(1), async {
+                "done"
+            }));
+        assert_eq!(result, Ok("done"));
+    }
+
+    #[test]
+    fn elapses() {
+        let result =
+            trpl::block_on(trpl::timeout(Duration::from_millis(1), async {
+                trpl::sleep(Duration::from_secs(10)).await
+            }));
+
+        let error: trpl::Elapsed = result.unwrap_err();
+        assert_eq!(error.to_string(), "deadline has elapsed");
+    }
+}
+
+mod cancellation {
+    use std::time::Duration;
+
+    use trpl::{CancellationToken, Either};
+
+    #[test]
+    fn stops_spawned_task() {
+        let ticks = trpl::block_on(async {
+            let token = CancellationToken::new();
+
+            let task = trpl::spawn_task({
+                let token = token.clone();
+                async move {
+                    let mut ticks = 0;
+                    loop {
+                        match trpl::select(
+                            token.cancelled(),
+                            trpl::sleep(Duration::from_millis(1)),
+                        )
+                        .await
+                        {
+                            Either::Left(()) => break ticks,
+                            Either::Right(()) => ticks += 1,
+                        }
+                    }
+                }
+            });
+
+            trpl::sleep(Duration::from_millis(10)).await;
+            token.cancel();
+            task.await.unwrap()
+        });
+
+        assert!(ticks > 0);
+    }
+
+    #[test]
+    fn child_tokens() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+
+        child.cancel();
+        assert!(!parent.is_cancelled());
+
+        let other = parent.child_token();
+        parent.cancel();
+        assert!(other.is_cancelled());
+    }
+}
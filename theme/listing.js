@@ -0,0 +1,147 @@
+// @ts-check
+
+/**
+ * The classes for the lines a listing calls out with its `highlight` and `dim`
+ * attributes, which the preprocessor puts on its `<figure>` as `data-highlight`
+ * and `data-dim`.
+ *
+ * @type {Array<{ attr: "highlight" | "dim", className: string }>}
+ */
+const LINE_MARKS = [
+  { attr: "highlight", className: "highlight-line" },
+  { attr: "dim", className: "dim-line" },
+];
+
+document.addEventListener("DOMContentLoaded", () => {
+  for (let figure of document.querySelectorAll("figure.listing")) {
+    if (figure instanceof HTMLElement) {
+      markLines(figure);
+    }
+  }
+});
+
+/**
+ * Wrap each highlighted or dimmed line of the listing's code in a `<span>`.
+ * Lines are counted as in the Markdown source, continuing across the listing's
+ * code blocks, so hidden lines count too.
+ *
+ * @param {HTMLElement} figure
+ */
+function markLines(figure) {
+  let marks = LINE_MARKS.map(({ attr, className }) => ({
+    ranges: parseRanges(figure.dataset[attr] ?? ""),
+    className,
+  })).filter(({ ranges }) => ranges.length > 0);
+
+  if (marks.length === 0) {
+    return;
+  }
+
+  let firstLine = 1;
+  for (let code of figure.querySelectorAll("pre > code")) {
+    let lines = sourceLines(code);
+    // Wrap from the end so that splitting the text of one line does not move
+    // the start and end of the lines before it.
+    for (let index = lines.length - 1; index >= 0; index--) {
+      let number = firstLine + index;
+      let mark = marks.find(({ ranges }) =>
+        ranges.some(([start, end]) => start <= number && number <= end),
+      );
+      if (mark) {
+        wrap(lines[index], mark.className);
+      }
+    }
+    firstLine += lines.length;
+  }
+}
+
+/**
+ * Parse a list of lines and ranges of lines like `3-5, 9`.
+ *
+ * @param {string} value
+ * @returns {Array<[number, number]>}
+ */
+function parseRanges(value) {
+  return value
+    .split(",")
+    .map((part) => part.trim())
+    .filter((part) => part.length > 0)
+    .map((part) => {
+      let [start, end = start] = part.split("-").map(Number);
+      return /** @type {[number, number]} */ ([start, end]);
+    });
+}
+
+/**
+ * Get a DOM range for each line of a rendered code block which was in the
+ * Markdown source, not including its line break.
+ *
+ * @param {Element} code
+ * @returns {Array<Range>}
+ */
+function sourceLines(code) {
+  /** @type {Array<Range>} */
+  let lines = [];
+  let walker = document.createTreeWalker(code, NodeFilter.SHOW_TEXT);
+  let line = document.createRange();
+  line.setStart(code, 0);
+
+  for (let node = walker.nextNode(); node; node = walker.nextNode()) {
+    let text = node.textContent ?? "";
+    let offset = text.indexOf("\n");
+    while (offset !== -1) {
+      line.setEnd(node, offset);
+      lines.push(line);
+      line = document.createRange();
+      line.setStart(node, offset + 1);
+      offset = text.indexOf("\n", offset + 1);
+    }
+  }
+
+  line.setEnd(code, code.childNodes.length);
+  if (line.toString().length > 0) {
+    lines.push(line);
+  }
+
+  return withoutPlaygroundWrapper(code, lines);
+}
+
+/**
+ * mdbook wraps Rust code without a `main` function in hidden lines to make it
+ * runnable on the playground: `#![allow(unused)]` first, then `fn main() {`
+ * after any inner attributes, and `}` last. Those lines were never part of
+ * the listing, so they are left out of its count.
+ *
+ * @param {Element} code
+ * @param {Array<Range>} lines
+ * @returns {Array<Range>}
+ */
+function withoutPlaygroundWrapper(code, lines) {
+  let text = lines.map((line) => line.toString().trim());
+  let isWrapped =
+    code.parentElement?.classList.contains("playground") &&
+    text[0] === "#![allow(unused)]" &&
+    ["}", "#}"].includes(text[text.length - 1]);
+  if (!isWrapped) {
+    return lines;
+  }
+
+  let main = text.findIndex((line) =>
+    ["fn main() {", "#fn main() {"].includes(line),
+  );
+  let last = lines.length - 1;
+  return lines.filter(
+    (_, index) => index !== 0 && index !== main && index !== last,
+  );
+}
+
+/**
+ * @param {Range} line
+ * @param {string} className
+ */
+function wrap(line, className) {
+  let span = document.createElement("span");
+  span.classList.add(className);
+  span.appendChild(line.extractContents());
+  line.insertNode(span);
+}
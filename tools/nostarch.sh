@@ -25,8 +25,7 @@ while IFS= read -r filename; do
   < "tmp/markdown/$filename" ./target/release/remove_links \
     | ./target/release/link2print \
     | ./target/release/remove_markup \
-    | ./target/release/remove_hidden_lines \
-    | ./target/release/cleanup_blockquotes > "tmp/$filename"
+    | ./target/release/remove_hidden_lines > "tmp/$filename"
 done
 # Concatenate the files into the `nostarch` dir.
 ./target/release/concat_chapters tmp nostarch
@@ -65,10 +65,18 @@ find -s listings -name output.txt -print0 | while IFS= read -r -d '' f; do
     # Clear the output file of everything except the command
     echo "$ ${cargo_command}" > "${full_output_path}"
 
+    # Listings which use `trpl` run their futures on its deterministic runtime,
+    # so that the interleaving of async output is the same on every run.
+    run_command="${cargo_command}"
+    if grep -q '^trpl' Cargo.toml && [ -n "${cargo_command}" ]; then
+        read -r -a words <<< "${cargo_command}"
+        run_command="${words[*]:0:2} --features trpl/deterministic ${words[*]:2}"
+    fi
+
     # Regenerate the output and append to the output file. Turn some warnings
     # off to reduce output noise, and use one test thread to get consistent
     # ordering of tests in the output when the command is `cargo test`.
-    RUSTFLAGS="-A unused_variables -A dead_code" RUST_TEST_THREADS=1 $cargo_command >> "${full_output_path}" 2>&1 || true
+    RUSTFLAGS="-A unused_variables -A dead_code" RUST_TEST_THREADS=1 $run_command >> "${full_output_path}" 2>&1 || true
 
     # Set the project file path to the projects directory plus the crate name
     # instead of a path to the computer of whoever is running this